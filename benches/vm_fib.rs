@@ -0,0 +1,71 @@
+// Times the tree-walking interpreter against the bytecode VM on the same program.
+// Run with `cargo bench --bench vm_fib`.
+use std::time::{Duration, Instant};
+
+use loquora::Value;
+use loquora::loquora::interpreter::Interpreter;
+use loquora::loquora::lexer::Lexer;
+use loquora::loquora::parser::Parser;
+use loquora::loquora::vm::{self, Vm};
+
+const SOURCE: &str = "
+tool fib(n: Int) -> Int {
+    let a = 0;
+    let b = 1;
+    let i = 0;
+    while i < n {
+        let next = a + b;
+        a = b;
+        b = next;
+        i = i + 1;
+    }
+    return a;
+}
+
+tool fib_rec(n: Int) -> Int {
+    if n < 2 {
+        return n;
+    }
+    return fib_rec(n - 1) + fib_rec(n - 2);
+}
+
+let total = 0;
+let round = 0;
+while round < 2000 {
+    total = (total + fib(60)) % 1000003;
+    round = round + 1;
+}
+total + fib_rec(20);
+";
+
+const RUNS: u32 = 5;
+
+fn time<F: FnMut() -> Value>(mut run: F) -> (Duration, Value) {
+    let mut result = Value::Null;
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        result = run();
+    }
+    (start.elapsed() / RUNS, result)
+}
+
+fn main() {
+    let program = Parser::new(Lexer::new(SOURCE.to_string())).parse_program();
+    let compiled = vm::compile(&program).expect("benchmark program should compile");
+
+    let (walker, walker_result) = time(|| {
+        Interpreter::new()
+            .interpret_program(&program)
+            .expect("interpreter run failed")
+    });
+    let (machine, machine_result) = time(|| Vm::new().run(&compiled).expect("vm run failed"));
+
+    assert_eq!(walker_result, machine_result);
+    println!("result:      {}", machine_result);
+    println!("interpreter: {:?} per run", walker);
+    println!("vm:          {:?} per run", machine);
+    println!(
+        "speedup:     {:.2}x",
+        walker.as_secs_f64() / machine.as_secs_f64()
+    );
+}
@@ -0,0 +1,4 @@
+pub mod loquora;
+
+pub use loquora::value::Value;
+pub use loquora::{LoquoraError, eval};
@@ -1,4 +1,5 @@
 use crate::loquora::token::{Span, TokenKind};
+use std::fmt;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Spanned<T> {
@@ -18,6 +19,8 @@ pub enum ExprKind {
     Int(i64),
     Float(f64),
     String(String),
+    // "a ${b} c", built from a string literal by the parser
+    Interpolation(Vec<InterpolationPart>),
     Char(char),
     Bool(bool),
     Null,
@@ -30,11 +33,21 @@ pub enum ExprKind {
         op: TokenKind,
         expr: Box<Expr>,
     },
+    // a < b <= c, each operand evaluated at most once
+    ComparisonChain {
+        operands: Vec<Expr>,
+        ops: Vec<TokenKind>,
+    },
     Ternary {
         cond: Box<Expr>,
         if_true: Box<Expr>,
         if_false: Box<Expr>,
     },
+    // a ?: b and a ?? b, b only evaluated when a is null
+    Coalesce {
+        value: Box<Expr>,
+        fallback: Box<Expr>,
+    },
     Quaternary {
         cond: Box<Expr>,
         if_true: Box<Expr>,
@@ -53,6 +66,26 @@ pub enum ExprKind {
         type_expr: Box<Expr>,
         fields: Vec<FieldInit>,
     },
+    // #{ key: value, ... }
+    MapLiteral {
+        entries: Vec<(Expr, Expr)>,
+    },
+    // [a, b, ...rest]
+    ListLiteral {
+        items: Vec<Expr>,
+    },
+    // `...xs` in call arguments and list literals, splicing in the items of a list
+    Spread(Box<Expr>),
+    // `loop { ... }` in expression position; its value is the one `break` carries out
+    Loop {
+        body: Vec<Stmt>,
+    },
+    // `if` in expression position; its value is the taken branch's final expression,
+    // or null when no branch is taken
+    IfExpr {
+        arms: Vec<(Expr, Vec<Stmt>)>,
+        else_body: Option<Vec<Stmt>>,
+    },
 }
 
 pub type Expr = Spanned<ExprKind>;
@@ -61,6 +94,38 @@ pub type Expr = Spanned<ExprKind>;
 pub enum TypeExprKind {
     Name(String),
     Generic { name: String, params: Vec<TypeExpr> },
+    // `T?` in tool signatures, also admitting null
+    Optional(Box<TypeExpr>),
+}
+
+impl TypeExprKind {
+    /// Outer type name, ignoring any generic parameters.
+    pub fn base_name(&self) -> &str {
+        match self {
+            TypeExprKind::Name(name) => name,
+            TypeExprKind::Generic { name, .. } => name,
+            TypeExprKind::Optional(inner) => inner.inner.base_name(),
+        }
+    }
+}
+
+impl fmt::Display for TypeExprKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeExprKind::Name(name) => write!(f, "{}", name),
+            TypeExprKind::Generic { name, params } => {
+                write!(f, "{}<", name)?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param.inner)?;
+                }
+                write!(f, ">")
+            }
+            TypeExprKind::Optional(inner) => write!(f, "{}?", inner.inner),
+        }
+    }
 }
 
 pub type TypeExpr = Spanned<TypeExprKind>;
@@ -69,6 +134,7 @@ pub type TypeExpr = Spanned<TypeExprKind>;
 pub struct ParamDecl {
     pub name: String,
     pub ty: TypeExpr,
+    pub default: Option<Expr>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -84,6 +150,10 @@ pub enum StmtKind {
     ExportDecl {
         decl: Box<Stmt>,
     },
+    // export { tool a() {} struct B {} }
+    ExportBlock {
+        decls: Vec<Stmt>,
+    },
     StructDecl {
         name: String,
         members: Vec<StructMember>,
@@ -103,11 +173,23 @@ pub enum StmtKind {
         target: Vec<String>,
         value: Expr,
     },
+    // `let name = value;` always binds in the current scope, shadowing any outer `name`
+    Let {
+        name: String,
+        value: Expr,
+    },
+    // `[a, b, ...rest] = xs;` or `{ x, y } = point;`, declaring the names when led by `let`
+    Destructure {
+        pattern: DestructurePattern,
+        value: Expr,
+        declare: bool,
+    },
     ExprStmt {
         expr: Expr,
     },
     With {
         expr: Expr,
+        binding: Option<String>,
         body: Vec<Stmt>,
     },
     Loop {
@@ -117,19 +199,37 @@ pub enum StmtKind {
         arms: Vec<(Expr, Vec<Stmt>)>,
         else_body: Option<Vec<Stmt>>,
     },
+    Match {
+        subject: Expr,
+        // each arm lists one or more patterns separated by `|`
+        arms: Vec<(Vec<Expr>, Vec<Stmt>)>,
+        default: Option<Vec<Stmt>>,
+    },
     While {
         cond: Expr,
         body: Vec<Stmt>,
+        // runs only when the loop ends without `break`
+        else_body: Option<Vec<Stmt>>,
+    },
+    DoWhile {
+        body: Vec<Stmt>,
+        cond: Expr,
     },
     For {
+        // `for i, x in ...` binds the index (or map key) here
+        index: Option<String>,
         var: String,
         iter: Expr,
         body: Vec<Stmt>,
+        else_body: Option<Vec<Stmt>>,
     },
     Return {
         expr: Option<Expr>,
     },
-    Break,
+    // the value is the result of the enclosing `loop`; other loops discard it
+    Break {
+        expr: Option<Expr>,
+    },
     Continue,
 }
 
@@ -153,6 +253,25 @@ pub enum StructMember {
 
 pub type Stmt = Spanned<StmtKind>;
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum DestructurePattern {
+    // binds items by position; `rest` collects whatever follows them
+    List {
+        names: Vec<String>,
+        rest: Option<String>,
+    },
+    // binds each named field to a variable of the same name
+    Object {
+        fields: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InterpolationPart {
+    Literal(String),
+    Expr(Expr),
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct FieldInit {
     pub name: String,
@@ -1,12 +1,15 @@
-use crate::loquora::ast::{ParamDecl, Stmt, StructMember};
+use crate::loquora::ast::{ParamDecl, Stmt, StructMember, TypeExpr};
 use crate::loquora::value::{RuntimeError, Value};
+use indexmap::IndexMap;
 use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum TypeDef {
     Struct {
         name: String,
-        members: Vec<StructMember>,
+        members: StructMembers,
     },
     Template {
         name: String,
@@ -15,10 +18,37 @@ pub enum TypeDef {
     },
 }
 
+/// A struct's fields and methods, shared by its declaration and every object built from it,
+/// so copying either never copies the method bodies. `Arc` rather than `Rc` keeps values `Send`.
+#[derive(Clone, Debug)]
+pub struct StructMembers(Arc<[StructMember]>);
+
+impl From<Vec<StructMember>> for StructMembers {
+    fn from(members: Vec<StructMember>) -> Self {
+        StructMembers(members.into())
+    }
+}
+
+impl Deref for StructMembers {
+    type Target = [StructMember];
+
+    fn deref(&self) -> &[StructMember] {
+        &self.0
+    }
+}
+
+// instances of one declaration share the allocation, so they compare without walking it
+impl PartialEq for StructMembers {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ToolDef {
     pub name: String,
     pub params: Vec<ParamDecl>,
+    pub return_type: Option<TypeExpr>,
     pub body: Vec<Stmt>,
 }
 
@@ -27,7 +57,17 @@ pub struct Environment {
     pub global_tools: HashMap<String, ToolDef>,
     pub type_definitions: HashMap<String, TypeDef>,
     pub in_loop: usize,
-    pub in_tool: bool,
+    pub in_tool: usize,
+    // index of each active tool call's own frame, innermost last
+    tool_frames: Vec<usize>,
+    // when false, redeclaring a top-level tool or type is an error instead of an overwrite
+    pub allow_shadowing: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
@@ -37,107 +77,486 @@ impl Environment {
             global_tools: HashMap::new(),
             type_definitions: HashMap::new(),
             in_loop: 0,
-            in_tool: false,
+            in_tool: 0,
+            tool_frames: Vec::new(),
+            allow_shadowing: false,
         }
     }
 
     pub fn get(&self, name: &str) -> Result<Value, RuntimeError> {
+        // check local variables from innermost to outermost scope
+        for frame in self.frames.iter().rev() {
+            if let Some(value) = frame.get(name) {
+                return Ok(value.clone());
+            }
+        }
+
+        // check global tools, we don't have local tools yet
+        if let Some(tool_def) = self.global_tools.get(name) {
+            return Ok(Value::ToolRef {
+                name: tool_def.name.clone(),
+                params: tool_def.params.clone(),
+                return_type: tool_def.return_type.clone(),
+                body: tool_def.body.clone(),
+            });
+        }
+
+        // builtins come last so user code can reuse names like `min`/`max`
+        self.get_builtin(name)
+    }
+
+    fn get_builtin(&self, name: &str) -> Result<Value, RuntimeError> {
         // TODO: replace with a proper built-in function implementation
         // standard library
         let builtin_result = match name {
             "print" => Some(Value::ToolRef {
                 name: "print".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "panic" => Some(Value::ToolRef {
                 name: "panic".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "list" => Some(Value::ToolRef {
                 name: "list".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "cons" => Some(Value::ToolRef {
                 name: "cons".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "nil" => Some(Value::List(vec![])),
             "object" => Some(Value::ToolRef {
                 name: "object".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "pair" => Some(Value::ToolRef {
                 name: "pair".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "get" => Some(Value::ToolRef {
                 name: "get".to_string(),
                 params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "get_or" => Some(Value::ToolRef {
+                name: "get_or".to_string(),
+                params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "lookup" => Some(Value::ToolRef {
                 name: "lookup".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "int" => Some(Value::ToolRef {
                 name: "int".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "float" => Some(Value::ToolRef {
                 name: "float".to_string(),
                 params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "parse_int" => Some(Value::ToolRef {
+                name: "parse_int".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "parse_float" => Some(Value::ToolRef {
+                name: "parse_float".to_string(),
+                params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "bool" => Some(Value::ToolRef {
                 name: "bool".to_string(),
                 params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             "str" => Some(Value::ToolRef {
                 name: "str".to_string(),
                 params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "repr" => Some(Value::ToolRef {
+                name: "repr".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "clone" => Some(Value::ToolRef {
+                name: "clone".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "abs" => Some(Value::ToolRef {
+                name: "abs".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "min" => Some(Value::ToolRef {
+                name: "min".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "max" => Some(Value::ToolRef {
+                name: "max".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "floor" => Some(Value::ToolRef {
+                name: "floor".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "ceil" => Some(Value::ToolRef {
+                name: "ceil".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "round" => Some(Value::ToolRef {
+                name: "round".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "sqrt" => Some(Value::ToolRef {
+                name: "sqrt".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "sign" => Some(Value::ToolRef {
+                name: "sign".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "gcd" => Some(Value::ToolRef {
+                name: "gcd".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "lcm" => Some(Value::ToolRef {
+                name: "lcm".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "range" => Some(Value::ToolRef {
+                name: "range".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "push" => Some(Value::ToolRef {
+                name: "push".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "pop" => Some(Value::ToolRef {
+                name: "pop".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "insert" => Some(Value::ToolRef {
+                name: "insert".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "remove" => Some(Value::ToolRef {
+                name: "remove".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "concat" => Some(Value::ToolRef {
+                name: "concat".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "sort" => Some(Value::ToolRef {
+                name: "sort".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "sorted" => Some(Value::ToolRef {
+                name: "sorted".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "reverse" => Some(Value::ToolRef {
+                name: "reverse".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "enumerate" => Some(Value::ToolRef {
+                name: "enumerate".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "memo" => Some(Value::ToolRef {
+                name: "memo".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "now" => Some(Value::ToolRef {
+                name: "now".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "read_file" => Some(Value::ToolRef {
+                name: "read_file".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "write_file" => Some(Value::ToolRef {
+                name: "write_file".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "div" => Some(Value::ToolRef {
+                name: "div".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "divmod" => Some(Value::ToolRef {
+                name: "divmod".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "zip" => Some(Value::ToolRef {
+                name: "zip".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "has_field" => Some(Value::ToolRef {
+                name: "has_field".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "remove_field" => Some(Value::ToolRef {
+                name: "remove_field".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "contains" => Some(Value::ToolRef {
+                name: "contains".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "index_of" => Some(Value::ToolRef {
+                name: "index_of".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "slice" => Some(Value::ToolRef {
+                name: "slice".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "trim" => Some(Value::ToolRef {
+                name: "trim".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "trim_start" => Some(Value::ToolRef {
+                name: "trim_start".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "trim_end" => Some(Value::ToolRef {
+                name: "trim_end".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "lines" => Some(Value::ToolRef {
+                name: "lines".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "split_lines" => Some(Value::ToolRef {
+                name: "split_lines".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "upper" => Some(Value::ToolRef {
+                name: "upper".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "lower" => Some(Value::ToolRef {
+                name: "lower".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "replace" => Some(Value::ToolRef {
+                name: "replace".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "starts_with" => Some(Value::ToolRef {
+                name: "starts_with".to_string(),
+                params: vec![],
+                return_type: None,
+                body: vec![],
+            }),
+            "ends_with" => Some(Value::ToolRef {
+                name: "ends_with".to_string(),
+                params: vec![],
+                return_type: None,
                 body: vec![],
             }),
             _ => None,
         };
 
-        if let Some(builtin_value) = builtin_result {
-            return Ok(builtin_value);
+        builtin_result.ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))
+    }
+
+    // every scope's variables, the global scope first
+    pub fn frames(&self) -> &[HashMap<String, Value>] {
+        &self.frames
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        if let Some(current_frame) = self.frames.last_mut() {
+            current_frame.insert(name.to_string(), value);
         }
+    }
 
-        // check local variables from innermost to outermost scope
-        for frame in self.frames.iter().rev() {
-            if let Some(value) = frame.get(name) {
-                return Ok(value.clone());
+    // frames an assignment can reach: those of the running tool call, or every frame
+    // at the top level; callers' locals are readable but never written
+    fn assignable_frames(&mut self) -> &mut [HashMap<String, Value>] {
+        let base = self.tool_frames.last().copied().unwrap_or(0);
+        &mut self.frames[base..]
+    }
+
+    // `name = value`: updates the nearest binding the running code can assign to; only
+    // `let` introduces a new one
+    pub fn assign(&mut self, name: &str, value: Value) -> Result<(), RuntimeError> {
+        let existing = self
+            .assignable_frames()
+            .iter_mut()
+            .rev()
+            .find_map(|frame| frame.get_mut(name));
+        match existing {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
             }
+            None => Err(self.unassignable(name)),
         }
+    }
 
-        // check global tools, we don't have local tools yet
-        if let Some(tool_def) = self.global_tools.get(name) {
-            return Ok(Value::ToolRef {
-                name: tool_def.name.clone(),
-                params: tool_def.params.clone(),
-                body: tool_def.body.clone(),
-            });
+    // why `name = ...` found nothing to update: the name is either undeclared or belongs
+    // to a scope outside the running tool
+    pub fn unassignable(&self, name: &str) -> RuntimeError {
+        if self.has_variable(name) {
+            RuntimeError::Custom(format!(
+                "Cannot assign to {} from inside a tool; it belongs to an outer scope",
+                name
+            ))
+        } else {
+            RuntimeError::UndeclaredAssignment(name.to_string())
         }
-
-        Err(RuntimeError::UndefinedVariable(name.to_string()))
     }
 
-    pub fn set(&mut self, name: &str, value: Value) {
-        if let Some(current_frame) = self.frames.last_mut() {
-            current_frame.insert(name.to_string(), value);
+    // appends to the list `name = ...` would assign to without copying it; returns
+    // false when that binding is missing or isn't a list, so the caller falls back
+    pub fn push_to_list(&mut self, name: &str, value: Value) -> bool {
+        match self
+            .assignable_frames()
+            .iter_mut()
+            .rev()
+            .find_map(|frame| frame.get_mut(name))
+        {
+            Some(Value::List(items)) => {
+                items.push(value);
+                true
+            }
+            _ => false,
         }
     }
 
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.frames.iter().any(|frame| frame.contains_key(name))
+    }
+
+    // true when `name` is a struct or type in scope, so type annotations check against it
+    pub fn declares_type(&self, name: &str) -> bool {
+        self.type_definitions.contains_key(name)
+            || self
+                .frames
+                .iter()
+                .rev()
+                .find_map(|frame| frame.get(name))
+                .is_some_and(|value| matches!(value, Value::TypeRef(_)))
+    }
+
+    // true when `name` isn't bound by user code, so it still means the builtin
+    pub fn is_builtin_name(&self, name: &str) -> bool {
+        !self.global_tools.contains_key(name)
+            && !self.has_variable(name)
+            && self.get_builtin(name).is_ok()
+    }
+
     pub fn set_path(&mut self, path: &[String], value: Value) -> Result<(), RuntimeError> {
         if path.is_empty() {
             return Err(RuntimeError::EmptyPath);
@@ -145,8 +564,7 @@ impl Environment {
 
         if path.len() == 1 {
             // x = value
-            self.set(&path[0], value);
-            return Ok(());
+            return self.assign(&path[0], value);
         }
 
         // a.b.c = value
@@ -155,8 +573,7 @@ impl Environment {
 
         // update recursively nested object
         let new_root = self.update_nested_object(root_value, &path[1..], value)?;
-        self.set(root_name, new_root);
-        Ok(())
+        self.assign(root_name, new_root)
     }
 
     fn update_nested_object(
@@ -190,6 +607,25 @@ impl Environment {
         }
     }
 
+    // drops scopes and loop/tool counters left behind by an error, keeping globals
+    pub fn unwind_to_global(&mut self) {
+        self.frames.truncate(1);
+        self.in_loop = 0;
+        self.in_tool = 0;
+        self.tool_frames.clear();
+    }
+
+    // forgets every variable, globals included; tools and types are kept
+    pub fn clear_frames(&mut self) {
+        self.unwind_to_global();
+        self.frames[0].clear();
+    }
+
+    // forgets every struct and template declaration
+    pub fn clear_types(&mut self) {
+        self.type_definitions.clear();
+    }
+
     pub fn enter_loop(&mut self) {
         self.in_loop += 1;
     }
@@ -204,40 +640,71 @@ impl Environment {
         self.in_loop > 0
     }
 
+    // called once the tool's own frame has been pushed
     pub fn enter_tool(&mut self) {
-        self.in_tool = true;
+        self.in_tool += 1;
+        self.tool_frames.push(self.frames.len() - 1);
     }
 
     pub fn exit_tool(&mut self) {
-        self.in_tool = false;
+        if self.in_tool > 0 {
+            self.in_tool -= 1;
+        }
+        self.tool_frames.pop();
     }
 
     pub fn is_in_tool(&self) -> bool {
-        self.in_tool
+        self.in_tool > 0
     }
 
-    pub fn define_tool(&mut self, name: String, params: Vec<ParamDecl>, body: Vec<Stmt>) {
-        self.global_tools
-            .insert(name.clone(), ToolDef { name, params, body });
+    pub fn define_tool(
+        &mut self,
+        name: String,
+        params: Vec<ParamDecl>,
+        return_type: Option<TypeExpr>,
+        body: Vec<Stmt>,
+    ) -> Result<(), RuntimeError> {
+        let tool_def = ToolDef {
+            name: name.clone(),
+            params,
+            return_type,
+            body,
+        };
+        // re-binding the same declaration, e.g. loading a module twice, is not a conflict
+        if let Some(existing) = self.global_tools.get(&name)
+            && *existing != tool_def
+            && !self.allow_shadowing
+        {
+            return Err(RuntimeError::DuplicateDefinition(format!("tool {}", name)));
+        }
+        self.global_tools.insert(name, tool_def);
+        Ok(())
     }
 
-    pub fn define_type(&mut self, type_def: TypeDef) {
+    pub fn define_type(&mut self, type_def: TypeDef) -> Result<(), RuntimeError> {
         let name = match &type_def {
             TypeDef::Struct { name, .. } => name.clone(),
             TypeDef::Template { name, .. } => name.clone(),
         };
+        if let Some(existing) = self.type_definitions.get(&name)
+            && *existing != type_def
+            && !self.allow_shadowing
+        {
+            return Err(RuntimeError::DuplicateDefinition(format!("type {}", name)));
+        }
         self.type_definitions.insert(name, type_def);
+        Ok(())
     }
 
     pub fn create_object_from_typedef(
         &self,
         type_def: &TypeDef,
-        field_values: HashMap<String, Value>,
+        mut field_values: IndexMap<String, Value>,
     ) -> Result<Value, RuntimeError> {
         self.validate_object_fields(type_def, &field_values)?;
 
-        let type_name = match type_def {
-            TypeDef::Struct { name, .. } => name.clone(),
+        let (type_name, members) = match type_def {
+            TypeDef::Struct { name, members } => (name.clone(), members),
             TypeDef::Template { name, .. } => {
                 return Err(RuntimeError::InvalidArguments(format!(
                     "Cannot instantiate template {}",
@@ -246,24 +713,48 @@ impl Environment {
             }
         };
 
+        // keep fields in declaration order whatever order the initializer used
+        let mut fields = IndexMap::with_capacity(field_values.len());
+        for member in members.iter() {
+            if let StructMember::Field(field) = member
+                && let Some(value) = field_values.swap_remove(&field.name)
+            {
+                fields.insert(field.name.clone(), value);
+            }
+        }
+
         Ok(Value::Object {
             type_name,
-            fields: field_values,
+            fields,
+            members: members.clone(),
         })
     }
 
     fn validate_object_fields(
         &self,
         type_def: &TypeDef,
-        fields: &HashMap<String, Value>,
+        fields: &IndexMap<String, Value>,
     ) -> Result<(), RuntimeError> {
         match type_def {
-            TypeDef::Struct { members, .. } => {
-                for member in members {
+            TypeDef::Struct { name, members } => {
+                // reject typos before the required check reports the field they were meant to be
+                for provided in fields.keys() {
+                    let declared = members.iter().any(
+                        |member| matches!(member, StructMember::Field(field) if &field.name == provided),
+                    );
+                    if !declared {
+                        return Err(RuntimeError::FieldNotFound(format!(
+                            "{} on struct {}",
+                            provided, name
+                        )));
+                    }
+                }
+
+                for member in members.iter() {
                     if let StructMember::Field(field) = member {
                         let field_name = &field.name;
-                        let is_optional = field.suffix.as_ref().map_or(false, |s| s.contains('?'));
-                        let is_required = field.suffix.as_ref().map_or(true, |s| s.contains('!'));
+                        let is_optional = field.suffix.as_ref().is_some_and(|s| s.contains('?'));
+                        let is_required = field.suffix.as_ref().is_none_or(|s| s.contains('!'));
 
                         if is_required && !is_optional && !fields.contains_key(field_name) {
                             return Err(RuntimeError::RequiredFieldMissing(field_name.clone()));
@@ -271,11 +762,24 @@ impl Environment {
 
                         if let Some(value) = fields.get(field_name) {
                             let is_nullable =
-                                field.suffix.as_ref().map_or(false, |s| s.contains('?'));
-                            if !is_nullable && matches!(value, Value::Null) {
+                                field.suffix.as_ref().is_some_and(|s| s.contains('?'));
+                            if matches!(value, Value::Null) {
+                                if !is_nullable {
+                                    return Err(RuntimeError::TypeMismatch {
+                                        expected: "non-null".to_string(),
+                                        actual: "null".to_string(),
+                                    });
+                                }
+                            } else if !value
+                                .matches_type(&field.ty.inner, &|ty| self.declares_type(ty))
+                            {
                                 return Err(RuntimeError::TypeMismatch {
-                                    expected: "non-null".to_string(),
-                                    actual: "null".to_string(),
+                                    expected: format!(
+                                        "{} for field '{}'",
+                                        field.ty.inner.base_name(),
+                                        field_name
+                                    ),
+                                    actual: value.type_label(),
                                 });
                             }
                         }
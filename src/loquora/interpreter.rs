@@ -1,37 +1,308 @@
+use crate::loquora::LoquoraError;
 use crate::loquora::ast::*;
 use crate::loquora::environment::{Environment, TypeDef};
-use crate::loquora::module::ModuleCache;
-use crate::loquora::token::TokenKind;
-use crate::loquora::value::{RuntimeError, Value};
+use crate::loquora::lint::{self, LintWarning};
+use crate::loquora::module::{ModuleCache, ModuleHost};
+use crate::loquora::token::{Span, TokenKind};
+use crate::loquora::value::{MapKey, MemoKey, RuntimeError, Value};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub enum ControlFlow {
     None,
     Return(Value),
-    Break,
+    // carries the value of `break expr`, null for a bare `break`
+    Break(Value),
     Continue,
 }
 
+#[derive(Clone, Debug)]
+pub struct CallFrame {
+    pub name: String,
+    pub span: Span,
+    // the module file the call was written in, `span` then counting from its start;
+    // None when it is in the source being run
+    pub file: Option<PathBuf>,
+}
+
+// a tool wrapped by `memo` and the results it has returned so far
+struct Memo {
+    tool: Value,
+    cache: HashMap<Vec<MemoKey>, Value>,
+}
+
+// each Loquora call costs many native frames, so runaway recursion is stopped well before
+// it can overflow the stack
+pub(crate) const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+// nesting beyond this in == / != is almost certainly a runaway structure
+const MAX_EQUALITY_DEPTH: usize = 512;
+// names a `match` pattern treats as a type test unless user code has bound them
+const TYPE_PATTERNS: [&str; 11] = [
+    "Int", "Float", "String", "Char", "Bool", "Null", "List", "Map", "Object", "Tool", "Module",
+];
+
 pub struct Interpreter {
-    env: Environment,
+    pub(crate) env: Environment,
     module_cache: ModuleCache,
+    call_stack: Vec<CallFrame>,
+    error_trace: Vec<CallFrame>,
+    // > 0 while running a nested block, where declarations are scoped to the current frame
+    block_depth: usize,
+    // where `print` writes; stdout unless an embedder swaps it out
+    output: Box<dyn Write>,
+    max_call_depth: usize,
+    // indexed by the number in a memoized tool's `name#memoN`
+    memos: Vec<Memo>,
+    // read_file / write_file are refused when an embedder turns this off
+    allow_io: bool,
+    // which arguments of the call about to be made were written as `a / b`, and whether the
+    // last `return` was, so an Int mismatch on them can point at `~/`
+    pub(crate) division_args: Vec<bool>,
+    pub(crate) returned_division: bool,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
+    /// An interpreter with no definitions; run code with [`Interpreter::interpret_program`]
+    /// for an already parsed program or [`Interpreter::eval_source`] for source text.
     pub fn new() -> Self {
         Interpreter {
             env: Environment::new(),
             module_cache: ModuleCache::new(),
+            call_stack: Vec::new(),
+            error_trace: Vec::new(),
+            block_depth: 0,
+            output: Box::new(io::stdout()),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            memos: Vec::new(),
+            allow_io: true,
+            division_args: Vec::new(),
+            returned_division: false,
+        }
+    }
+
+    // a module's interpreter, running with the settings and output of the one loading it
+    pub(crate) fn for_module(host: ModuleHost) -> Self {
+        let mut interpreter = Self::with_output(host.output);
+        interpreter.allow_io = host.allow_io;
+        interpreter.max_call_depth = host.max_call_depth;
+        interpreter.set_allow_shadowing(host.allow_shadowing);
+        interpreter
+    }
+
+    // lends this interpreter's settings and output to a module it loads; the output
+    // comes back when the load finishes
+    pub(crate) fn module_host(&mut self) -> ModuleHost {
+        ModuleHost {
+            allow_io: self.allow_io,
+            max_call_depth: self.max_call_depth,
+            allow_shadowing: self.env.allow_shadowing,
+            output: std::mem::replace(&mut self.output, Box::new(io::sink())),
+        }
+    }
+
+    pub fn swap_module_cache(&mut self, cache: &mut ModuleCache) {
+        std::mem::swap(&mut self.module_cache, cache);
+    }
+
+    pub fn set_allow_shadowing(&mut self, allow: bool) {
+        self.env.allow_shadowing = allow;
+    }
+
+    /// Caps how many tool calls may be active at once; deeper calls fail with a
+    /// stack overflow error. Raising it past the default needs a bigger thread stack.
+    ///
+    /// ```
+    /// use loquora::loquora::interpreter::Interpreter;
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.set_max_call_depth(50);
+    /// let result = interpreter.eval_source("tool f(n: Int) -> Int { return f(n + 1); } f(0);");
+    /// assert!(result.unwrap_err().to_string().contains("stack overflow"));
+    /// ```
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Enables or disables the filesystem builtins `read_file` and `write_file`.
+    /// They are on by default; sandboxed embeddings should switch them off.
+    ///
+    /// ```
+    /// use loquora::loquora::interpreter::Interpreter;
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.set_allow_io(false);
+    /// let result = interpreter.eval_source("read_file(\"Cargo.toml\");");
+    /// assert!(result.unwrap_err().to_string().contains("file access is disabled"));
+    /// ```
+    ///
+    /// Modules run with `load_and_run` inherit the setting:
+    ///
+    /// ```
+    /// use loquora::loquora::interpreter::Interpreter;
+    /// use std::fs;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("loquora_sandbox_{}", std::process::id()));
+    /// fs::create_dir_all(&dir).unwrap();
+    /// fs::write(dir.join("evil.loq"), "write_file(\"pwned.txt\", \"gotcha\");").unwrap();
+    /// std::env::set_current_dir(&dir).unwrap();
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.set_allow_io(false);
+    /// let _ = interpreter.eval_source("load_and_run evil;");
+    /// assert!(!dir.join("pwned.txt").exists());
+    /// # fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn set_allow_io(&mut self, allow: bool) {
+        self.allow_io = allow;
+    }
+
+    /// Like [`Interpreter::new`], but `print` writes to `output` instead of stdout.
+    ///
+    /// ```
+    /// use loquora::loquora::interpreter::Interpreter;
+    /// use std::cell::RefCell;
+    /// use std::io::{self, Write};
+    /// use std::rc::Rc;
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct Captured(Rc<RefCell<Vec<u8>>>);
+    ///
+    /// impl Write for Captured {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         self.0.borrow_mut().write(buf)
+    ///     }
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let captured = Captured::default();
+    /// let mut interpreter = Interpreter::with_output(Box::new(captured.clone()));
+    /// interpreter.eval_source("print(\"hi\", 1 + 2);").unwrap();
+    /// assert_eq!(captured.0.borrow().as_slice(), b"hi 3\n");
+    ///
+    /// // modules run with `load_and_run` print to the same place
+    /// let dir = std::env::temp_dir().join(format!("loquora_output_{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("greeter.loq"), "print(\"from the module\");").unwrap();
+    /// std::env::set_current_dir(&dir).unwrap();
+    /// interpreter.eval_source("load_and_run greeter;").unwrap();
+    /// let printed = String::from_utf8(captured.0.borrow().clone()).unwrap();
+    /// assert!(printed.starts_with("hi 3\nfrom the module\nResult for file path "));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let mut interpreter = Self::new();
+        interpreter.set_output(output);
+        interpreter
+    }
+
+    /// Sends everything `print` writes from now on to `output`.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // the session's variables, tools and types, for the REPL to inspect
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    pub fn env_mut(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+
+    // frames of the tool calls active when the last runtime error was raised, innermost first
+    pub fn error_trace(&self) -> &[CallFrame] {
+        &self.error_trace
+    }
+
+    // called once the error is known, so only a failing call pays for finding each frame's file
+    fn capture_error_trace(&mut self) {
+        self.error_trace = self
+            .call_stack
+            .iter()
+            .rev()
+            .map(|frame| match self.module_cache.locate(&frame.span) {
+                Some((file, span)) => CallFrame {
+                    name: frame.name.clone(),
+                    span,
+                    file: Some(file),
+                },
+                None => frame.clone(),
+            })
+            .collect();
+    }
+
+    /// Runs a snippet on top of the definitions and globals left by earlier calls.
+    ///
+    /// This runs on the caller's stack. The default call depth limit needs a thread with
+    /// [`STACK_SIZE`](crate::loquora::STACK_SIZE) of stack; on a smaller one, lower it with
+    /// [`set_max_call_depth`](Self::set_max_call_depth) or runaway recursion can overflow.
+    ///
+    /// ```
+    /// use loquora::Value;
+    /// use loquora::loquora::interpreter::Interpreter;
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.eval_source("tool double(n: Int) -> Int { return n * 2; }").unwrap();
+    /// let value = interpreter.eval_source("double(21);").unwrap();
+    /// assert!(matches!(value, Value::Int(42)));
+    /// ```
+    pub fn eval_source(&mut self, src: &str) -> Result<Value, LoquoraError> {
+        let program = crate::loquora::parse_source(src)?;
+        let result = self.interpret_program(&program);
+        if result.is_err() {
+            self.env.unwind_to_global();
+            self.block_depth = 0;
         }
+        Ok(result?)
+    }
+
+    /// Unused tool parameters and bindings that shadow an outer one, for editors to flag.
+    /// Purely static: nothing is run and the program can still be interpreted as is.
+    pub fn lint(&self, program: &Program) -> Vec<LintWarning> {
+        lint::lint_program(program)
     }
 
     pub fn interpret_program(&mut self, program: &Program) -> Result<Value, RuntimeError> {
-        let last_value = Value::Null;
+        let mut last_value = Value::Null;
+        self.call_stack.clear();
+        self.error_trace.clear();
 
+        // the program's result is the value of its final expression statement
         for stmt in &program.statements {
-            match self.interpret_statement(stmt)? {
+            let flow = match &stmt.inner {
+                StmtKind::ExprStmt { expr } => {
+                    last_value = self.interpret_expression(expr)?;
+                    ControlFlow::None
+                }
+                // a `loop` ending the program gives its break value as the result
+                StmtKind::Loop { body } => match self.interpret_loop(body)? {
+                    ControlFlow::Break(value) => {
+                        last_value = value;
+                        ControlFlow::None
+                    }
+                    other => other,
+                },
+                _ => {
+                    last_value = Value::Null;
+                    self.interpret_statement(stmt)?
+                }
+            };
+            match flow {
                 ControlFlow::Return(value) => return Ok(value),
-                ControlFlow::Break => return Err(RuntimeError::BreakOutsideLoop),
+                ControlFlow::Break(_) => return Err(RuntimeError::BreakOutsideLoop),
                 ControlFlow::Continue => return Err(RuntimeError::ContinueOutsideLoop),
                 ControlFlow::None => {}
             }
@@ -43,11 +314,30 @@ impl Interpreter {
     fn interpret_statement(&mut self, stmt: &Stmt) -> Result<ControlFlow, RuntimeError> {
         match &stmt.inner {
             StmtKind::Assignment { target, value } => {
+                if self.try_push_in_place(target, value)? {
+                    return Ok(ControlFlow::None);
+                }
                 let val = self.interpret_expression(value)?;
                 self.env.set_path(target, val)?;
                 Ok(ControlFlow::None)
             }
 
+            StmtKind::Let { name, value } => {
+                let val = self.interpret_expression(value)?;
+                self.env.set(name, val);
+                Ok(ControlFlow::None)
+            }
+
+            StmtKind::Destructure {
+                pattern,
+                value,
+                declare,
+            } => {
+                let val = self.interpret_expression(value)?;
+                self.destructure(pattern, val, *declare)?;
+                Ok(ControlFlow::None)
+            }
+
             StmtKind::ExprStmt { expr } => {
                 self.interpret_expression(expr)?;
                 Ok(ControlFlow::None)
@@ -62,14 +352,19 @@ impl Interpreter {
                 } else {
                     Value::Null
                 };
+                self.returned_division = expr.as_ref().is_some_and(Self::is_division);
                 Ok(ControlFlow::Return(value))
             }
 
-            StmtKind::Break => {
+            StmtKind::Break { expr } => {
                 if !self.env.is_in_loop() {
                     return Err(RuntimeError::BreakOutsideLoop);
                 }
-                Ok(ControlFlow::Break)
+                let value = match expr {
+                    Some(expr) => self.interpret_expression(expr)?,
+                    None => Value::Null,
+                };
+                Ok(ControlFlow::Break(value))
             }
 
             StmtKind::Continue => {
@@ -82,20 +377,38 @@ impl Interpreter {
             StmtKind::ToolDecl {
                 name,
                 params,
-                return_type: _,
+                return_type,
                 body,
             } => {
-                self.env
-                    .define_tool(name.clone(), params.clone(), body.clone());
+                if self.block_depth > 0 {
+                    let tool = Value::ToolRef {
+                        name: name.clone(),
+                        params: params.clone(),
+                        return_type: return_type.clone(),
+                        body: body.clone(),
+                    };
+                    self.env.set(name, tool);
+                } else {
+                    self.env.define_tool(
+                        name.clone(),
+                        params.clone(),
+                        return_type.clone(),
+                        body.clone(),
+                    )?;
+                }
                 Ok(ControlFlow::None)
             }
 
             StmtKind::StructDecl { name, members } => {
                 let type_def = TypeDef::Struct {
                     name: name.clone(),
-                    members: members.clone(),
+                    members: members.clone().into(),
                 };
-                self.env.define_type(type_def);
+                if self.block_depth > 0 {
+                    self.env.set(name, Value::TypeRef(type_def));
+                } else {
+                    self.env.define_type(type_def)?;
+                }
                 Ok(ControlFlow::None)
             }
 
@@ -105,7 +418,7 @@ impl Interpreter {
                     params: params.clone(),
                     body: body.clone(),
                 };
-                self.env.define_type(type_def);
+                self.env.define_type(type_def)?;
                 Ok(ControlFlow::None)
             }
 
@@ -126,18 +439,49 @@ impl Interpreter {
                 }
             }
 
-            StmtKind::While { cond, body } => {
+            StmtKind::Match {
+                subject,
+                arms,
+                default,
+            } => {
+                let subject_value = self.interpret_expression(subject)?;
+                for (patterns, body) in arms {
+                    for pattern in patterns {
+                        if self.pattern_matches(&subject_value, pattern)? {
+                            return self.interpret_block(body);
+                        }
+                    }
+                }
+
+                match default {
+                    Some(body) => self.interpret_block(body),
+                    None => Err(RuntimeError::Custom(format!(
+                        "no match arm for {}",
+                        subject_value
+                    ))),
+                }
+            }
+
+            StmtKind::While {
+                cond,
+                body,
+                else_body,
+            } => {
                 self.env.enter_loop();
+                let mut broke = false;
                 loop {
                     let cond_value = self.interpret_expression(cond)?;
                     if !cond_value.is_truthy() {
                         break;
                     }
 
-                    let control = self.interpret_block(body)?;
+                    let control = self.interpret_iteration(body)?;
 
                     match control {
-                        ControlFlow::Break => break,
+                        ControlFlow::Break(_) => {
+                            broke = true;
+                            break;
+                        }
                         ControlFlow::Continue => continue,
                         ControlFlow::Return(value) => {
                             self.env.exit_loop();
@@ -147,69 +491,95 @@ impl Interpreter {
                     }
                 }
                 self.env.exit_loop();
-                Ok(ControlFlow::None)
+                self.interpret_loop_else(else_body, broke)
             }
 
-            StmtKind::Loop { body } => {
+            StmtKind::DoWhile { body, cond } => {
                 self.env.enter_loop();
                 loop {
-                    let control = self.interpret_block(body)?;
+                    let control = self.interpret_iteration(body)?;
 
                     match control {
-                        ControlFlow::Break => break,
-                        ControlFlow::Continue => continue,
+                        ControlFlow::Break(_) => break,
+                        // continue still goes through the condition check
+                        ControlFlow::Continue | ControlFlow::None => {}
                         ControlFlow::Return(value) => {
                             self.env.exit_loop();
                             return Ok(ControlFlow::Return(value));
                         }
-                        ControlFlow::None => {}
+                    }
+
+                    let cond_value = self.interpret_expression(cond)?;
+                    if !cond_value.is_truthy() {
+                        break;
                     }
                 }
                 self.env.exit_loop();
                 Ok(ControlFlow::None)
             }
 
-            StmtKind::For { var, iter, body } => {
+            StmtKind::Loop { body } => match self.interpret_loop(body)? {
+                ControlFlow::Break(_) => Ok(ControlFlow::None),
+                other => Ok(other),
+            },
+
+            StmtKind::For {
+                index,
+                var,
+                iter,
+                body,
+                else_body,
+            } => {
                 self.env.enter_loop();
-                self.env.push_scope();
 
                 let iter_value = self.interpret_expression(iter)?;
-
-                match iter_value {
-                    Value::List(items) => {
-                        for item in items {
-                            self.env.set_path(&vec![var.clone()], item)?;
-
-                            let control = self.interpret_block(body)?;
-
-                            match control {
-                                ControlFlow::Break => break,
-                                ControlFlow::Continue => continue,
-                                ControlFlow::Return(value) => {
-                                    self.env.pop_scope();
-                                    self.env.exit_loop();
-                                    return Ok(ControlFlow::Return(value));
-                                }
-                                ControlFlow::None => {}
-                            }
+                // a single variable over a map walks its keys, like the key half of a pair
+                let single_binds_key = matches!(iter_value, Value::Map(_));
+                let pairs = Self::iteration_pairs(iter_value)?;
+                let mut broke = false;
+
+                for (key, item) in pairs {
+                    // the loop variables belong to the iteration's own scope
+                    self.env.push_scope();
+                    match index {
+                        Some(index) => {
+                            self.env.set(index, key);
+                            self.env.set(var, item);
                         }
+                        None if single_binds_key => self.env.set(var, key),
+                        None => self.env.set(var, item),
                     }
-                    _ => {
-                        return Err(RuntimeError::Custom(format!(
-                            "Cannot iterate over {:?}",
-                            iter_value
-                        )));
+                    let control = self.interpret_block(body);
+                    self.env.pop_scope();
+
+                    match control? {
+                        ControlFlow::Break(_) => {
+                            broke = true;
+                            break;
+                        }
+                        ControlFlow::Continue => continue,
+                        ControlFlow::Return(value) => {
+                            self.env.exit_loop();
+                            return Ok(ControlFlow::Return(value));
+                        }
+                        ControlFlow::None => {}
                     }
                 }
 
-                self.env.pop_scope();
                 self.env.exit_loop();
-                Ok(ControlFlow::None)
+                self.interpret_loop_else(else_body, broke)
             }
 
-            StmtKind::With { expr, body } => {
-                let _with_value = self.interpret_expression(expr)?;
+            StmtKind::With {
+                expr,
+                binding,
+                body,
+            } => {
+                let with_value = self.interpret_expression(expr)?;
                 self.env.push_scope();
+                if let Some(name) = binding {
+                    self.env.set(name, with_value);
+                }
                 let result = self.interpret_block(body)?;
                 self.env.pop_scope();
                 Ok(result)
@@ -220,10 +590,73 @@ impl Interpreter {
             StmtKind::LoadAndRun { path, alias } => self.handle_load(path, alias, true),
 
             StmtKind::ExportDecl { decl } => self.interpret_statement(decl),
+
+            StmtKind::ExportBlock { decls } => {
+                for decl in decls {
+                    self.interpret_statement(decl)?;
+                }
+                Ok(ControlFlow::None)
+            }
+        }
+    }
+
+    // each pass through a loop body gets a fresh scope, so names first assigned inside
+    // it are gone when the iteration ends; assigning an existing name still updates it
+    fn interpret_iteration(&mut self, body: &[Stmt]) -> Result<ControlFlow, RuntimeError> {
+        self.env.push_scope();
+        let control = self.interpret_block(body);
+        self.env.pop_scope();
+        control
+    }
+
+    // the else block runs after the loop has been left, so break/continue inside it
+    // belong to any enclosing loop
+    fn interpret_loop_else(
+        &mut self,
+        else_body: &Option<Vec<Stmt>>,
+        broke: bool,
+    ) -> Result<ControlFlow, RuntimeError> {
+        match else_body {
+            Some(body) if !broke => self.interpret_block(body),
+            _ => Ok(ControlFlow::None),
+        }
+    }
+
+    // (index, element) for lists and strings, (key, value) for maps
+    fn iteration_pairs(iter_value: Value) -> Result<Vec<(Value, Value)>, RuntimeError> {
+        match iter_value {
+            Value::List(items) => Ok(items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| (Value::Int(i as i64), item))
+                .collect()),
+            Value::String(s) => Ok(s
+                .chars()
+                .enumerate()
+                .map(|(i, ch)| (Value::Int(i as i64), Value::Char(ch)))
+                .collect()),
+            Value::Map(entries) => Ok(entries
+                .into_iter()
+                .map(|(key, value)| (key.into_value(), value))
+                .collect()),
+            other => Err(RuntimeError::Custom(format!(
+                "Cannot iterate over {:?}",
+                other
+            ))),
         }
     }
 
     fn interpret_block(&mut self, statements: &[Stmt]) -> Result<ControlFlow, RuntimeError> {
+        self.block_depth += 1;
+        let result = self.interpret_block_statements(statements);
+        self.block_depth -= 1;
+        result
+    }
+
+    fn interpret_block_statements(
+        &mut self,
+        statements: &[Stmt],
+    ) -> Result<ControlFlow, RuntimeError> {
         for stmt in statements {
             let control = self.interpret_statement(stmt)?;
             match control {
@@ -239,6 +672,18 @@ impl Interpreter {
             ExprKind::Int(n) => Ok(Value::Int(*n)),
             ExprKind::Float(f) => Ok(Value::Float(*f)),
             ExprKind::String(s) => Ok(Value::String(s.clone())),
+            ExprKind::Interpolation(parts) => {
+                let mut out = String::new();
+                for part in parts {
+                    match part {
+                        InterpolationPart::Literal(text) => out.push_str(text),
+                        InterpolationPart::Expr(expr) => {
+                            out.push_str(&self.interpret_expression(expr)?.to_display_string())
+                        }
+                    }
+                }
+                Ok(Value::String(out))
+            }
             ExprKind::Char(c) => Ok(Value::Char(*c)),
             ExprKind::Bool(b) => Ok(Value::Bool(*b)),
             ExprKind::Null => Ok(Value::Null),
@@ -257,12 +702,24 @@ impl Interpreter {
 
             ExprKind::UnaryOp { op, expr } => self.interpret_unary_op(op, expr),
 
+            ExprKind::ComparisonChain { operands, ops } => {
+                let mut left = self.interpret_expression(&operands[0])?;
+                for (op, operand) in ops.iter().zip(&operands[1..]) {
+                    let right = self.interpret_expression(operand)?;
+                    if !self.compare_with_op(op, left, right.clone())?.is_truthy() {
+                        return Ok(Value::Bool(false));
+                    }
+                    left = right;
+                }
+                Ok(Value::Bool(true))
+            }
+
             ExprKind::Property { object, property } => {
                 let obj_value = self.interpret_expression(object)?;
                 obj_value.get_property(property)
             }
 
-            ExprKind::Call { callee, args } => self.interpret_call(callee, args),
+            ExprKind::Call { callee, args } => self.interpret_call(callee, args, &expr.span),
 
             ExprKind::Ternary {
                 cond,
@@ -277,6 +734,11 @@ impl Interpreter {
                 }
             }
 
+            ExprKind::Coalesce { value, fallback } => match self.interpret_expression(value)? {
+                Value::Null => self.interpret_expression(fallback),
+                value => Ok(value),
+            },
+
             ExprKind::Quaternary {
                 cond,
                 if_true,
@@ -301,6 +763,104 @@ impl Interpreter {
                     ))),
                 }
             }
+
+            ExprKind::MapLiteral { entries } => {
+                let mut map = IndexMap::new();
+                for (key, value) in entries {
+                    let key = MapKey::from_value(self.interpret_expression(key)?)?;
+                    let value = self.interpret_expression(value)?;
+                    map.insert(key, value);
+                }
+                Ok(Value::Map(map))
+            }
+
+            ExprKind::ListLiteral { items } => Ok(Value::List(self.interpret_items(items)?)),
+
+            // the parser only produces these inside call arguments and list literals
+            ExprKind::Spread(_) => Err(RuntimeError::Custom(
+                "`...` can only be used in call arguments and list literals".to_string(),
+            )),
+
+            ExprKind::IfExpr { arms, else_body } => self.interpret_if_value(arms, else_body),
+
+            ExprKind::Loop { body } => match self.interpret_loop(body)? {
+                ControlFlow::Break(value) => Ok(value),
+                // an expression has nowhere to send a `return` on to
+                _ => Err(RuntimeError::Custom(
+                    "cannot return from inside a loop expression; break with a value instead"
+                        .to_string(),
+                )),
+            },
+        }
+    }
+
+    fn interpret_if_value(
+        &mut self,
+        arms: &[(Expr, Vec<Stmt>)],
+        else_body: &Option<Vec<Stmt>>,
+    ) -> Result<Value, RuntimeError> {
+        for (condition, body) in arms {
+            if self.interpret_expression(condition)?.is_truthy() {
+                return self.interpret_branch_value(body);
+            }
+        }
+        match else_body {
+            Some(else_body) => self.interpret_branch_value(else_body),
+            None => Ok(Value::Null),
+        }
+    }
+
+    // the value of an `if` expression's branch: its final expression, which may itself be
+    // a nested `if`, or null when the branch ends in some other statement
+    fn interpret_branch_value(&mut self, body: &[Stmt]) -> Result<Value, RuntimeError> {
+        self.block_depth += 1;
+        let result = self.interpret_branch_statements(body);
+        self.block_depth -= 1;
+        result
+    }
+
+    fn interpret_branch_statements(&mut self, body: &[Stmt]) -> Result<Value, RuntimeError> {
+        let mut value = Value::Null;
+        for stmt in body {
+            match &stmt.inner {
+                StmtKind::ExprStmt { expr } => {
+                    value = self.interpret_expression(expr)?;
+                    continue;
+                }
+                StmtKind::If { arms, else_body } => {
+                    value = self.interpret_if_value(arms, else_body)?;
+                    continue;
+                }
+                _ => value = Value::Null,
+            }
+            if !matches!(self.interpret_statement(stmt)?, ControlFlow::None) {
+                // like a loop expression, there is nowhere to send control flow on to
+                return Err(RuntimeError::Custom(
+                    "cannot break, continue or return from inside an if expression".to_string(),
+                ));
+            }
+        }
+        Ok(value)
+    }
+
+    // runs until a `break`, which comes back as Break carrying its value, or a `return`
+    fn interpret_loop(&mut self, body: &[Stmt]) -> Result<ControlFlow, RuntimeError> {
+        self.env.enter_loop();
+        loop {
+            let control = self.interpret_iteration(body)?;
+
+            match control {
+                ControlFlow::Break(value) => {
+                    self.env.exit_loop();
+                    return Ok(ControlFlow::Break(value));
+                }
+                ControlFlow::Continue => continue,
+                ControlFlow::Return(value) => {
+                    self.env.exit_loop();
+                    return Ok(ControlFlow::Return(value));
+                }
+                ControlFlow::None => {}
+            }
         }
     }
 
@@ -311,6 +871,9 @@ impl Interpreter {
         right: &Expr,
     ) -> Result<Value, RuntimeError> {
         match op {
+            // && and || return the operand that decided the result rather than coercing to
+            // Bool, so `name || "anonymous"` works as a default; the right side only runs
+            // when the left does not decide it
             TokenKind::LogicalAnd => {
                 let left_val = self.interpret_expression(left)?;
                 if !left_val.is_truthy() {
@@ -330,54 +893,88 @@ impl Interpreter {
             _ => {
                 let left_val = self.interpret_expression(left)?;
                 let right_val = self.interpret_expression(right)?;
+                self.apply_binary_op(op, left_val, right_val)
+            }
+        }
+    }
 
-                match op {
-                    // arithmetic
-                    TokenKind::Plus => self.add_values(left_val, right_val),
-                    TokenKind::Minus => self.subtract_values(left_val, right_val),
-                    TokenKind::Multiply => self.multiply_values(left_val, right_val),
-                    TokenKind::Divide => self.divide_values(left_val, right_val),
-                    TokenKind::Modulo => self.modulo_values(left_val, right_val),
-                    // useless @ operator that returns lvalue
-                    // Loquora signature
-                    TokenKind::At => Ok(left_val),
-
-                    // bitwise
-                    TokenKind::BitAnd => self.bitwise_and(left_val, right_val),
-                    TokenKind::BitOr => self.bitwise_or(left_val, right_val),
-                    TokenKind::BitXor => self.bitwise_xor(left_val, right_val),
-                    TokenKind::ShiftLeft => self.shift_left(left_val, right_val),
-                    TokenKind::ShiftRight => self.shift_right(left_val, right_val),
-
-                    // comparison
-                    TokenKind::EqualEqual => {
-                        Ok(Value::Bool(self.values_equal(&left_val, &right_val)))
-                    }
-                    TokenKind::NotEqual => {
-                        Ok(Value::Bool(!self.values_equal(&left_val, &right_val)))
-                    }
-                    TokenKind::Less => self.compare_values(left_val, right_val, |a, b| a < b),
-                    TokenKind::Greater => self.compare_values(left_val, right_val, |a, b| a > b),
-                    TokenKind::LessEqual => self.compare_values(left_val, right_val, |a, b| a <= b),
-                    TokenKind::GreaterEqual => {
-                        self.compare_values(left_val, right_val, |a, b| a >= b)
-                    }
+    // the non-short-circuiting operators, once both operands are evaluated
+    pub(crate) fn apply_binary_op(
+        &mut self,
+        op: &TokenKind,
+        left_val: Value,
+        right_val: Value,
+    ) -> Result<Value, RuntimeError> {
+        match op {
+            // arithmetic
+            TokenKind::Plus => self.add_values(left_val, right_val),
+            TokenKind::Minus => self.subtract_values(left_val, right_val),
+            TokenKind::Multiply => self.multiply_values(left_val, right_val),
+            TokenKind::Divide => self.divide_values(left_val, right_val),
+            TokenKind::FloorDiv => self.floor_divide_values(left_val, right_val),
+            TokenKind::Modulo => self.modulo_values(left_val, right_val),
+            // useless @ operator that returns lvalue
+            // Loquora signature
+            TokenKind::At => Ok(left_val),
+
+            // bitwise
+            TokenKind::BitAnd => self.bitwise_and(left_val, right_val),
+            TokenKind::BitOr => self.bitwise_or(left_val, right_val),
+            TokenKind::BitXor => self.bitwise_xor(left_val, right_val),
+            TokenKind::ShiftLeft => self.shift_left(left_val, right_val),
+            TokenKind::ShiftRight => self.shift_right(left_val, right_val),
+
+            // comparison
+            TokenKind::EqualEqual => Ok(Value::Bool(self.values_equal(&left_val, &right_val)?)),
+            TokenKind::NotEqual => Ok(Value::Bool(!self.values_equal(&left_val, &right_val)?)),
+            TokenKind::Less
+            | TokenKind::Greater
+            | TokenKind::LessEqual
+            | TokenKind::GreaterEqual => self.compare_with_op(op, left_val, right_val),
+            TokenKind::In => Ok(Value::Bool(self.contains_value(&right_val, &left_val)?)),
 
-                    _ => Err(RuntimeError::Custom(format!(
-                        "Unsupported binary operator: {:?}",
-                        op
-                    ))),
+            _ => Err(RuntimeError::Custom(format!(
+                "Unsupported binary operator: {:?}",
+                op
+            ))),
+        }
+    }
+
+    // `needle in collection`
+    fn contains_value(&self, collection: &Value, needle: &Value) -> Result<bool, RuntimeError> {
+        match (collection, needle) {
+            (Value::List(items), needle) => {
+                for item in items {
+                    if self.values_equal(item, needle)? {
+                        return Ok(true);
+                    }
                 }
+                Ok(false)
+            }
+            (Value::String(haystack), Value::String(needle)) => {
+                Ok(haystack.contains(needle.as_str()))
             }
+            (Value::String(haystack), Value::Char(needle)) => Ok(haystack.contains(*needle)),
+            (Value::Map(entries), key) => {
+                Ok(entries.contains_key(&MapKey::from_value(key.clone())?))
+            }
+            (Value::Object { fields, .. }, Value::String(name)) => Ok(fields.contains_key(name)),
+            (collection, needle) => Err(RuntimeError::TypeMismatch {
+                expected: "List, Map, or String/Object with a String key for `in`".to_string(),
+                actual: format!("{} in {}", needle.type_name(), collection.type_name()),
+            }),
         }
     }
 
     fn interpret_unary_op(&mut self, op: &TokenKind, expr: &Expr) -> Result<Value, RuntimeError> {
         let val = self.interpret_expression(expr)?;
+        Self::apply_unary_op(op, val)
+    }
 
+    pub(crate) fn apply_unary_op(op: &TokenKind, val: Value) -> Result<Value, RuntimeError> {
         match op {
             TokenKind::Minus => match val {
-                Value::Int(n) => Ok(Value::Int(-n)),
+                Value::Int(n) => Self::checked_int(n.checked_neg()),
                 Value::Float(f) => Ok(Value::Float(-f)),
                 _ => Err(RuntimeError::TypeMismatch {
                     expected: "numeric".to_string(),
@@ -406,97 +1003,366 @@ impl Interpreter {
         }
     }
 
-    fn interpret_call(&mut self, callee: &Expr, args: &[Expr]) -> Result<Value, RuntimeError> {
-        let callee_value = self.interpret_expression(callee)?;
-        self.interpret_call_value(callee_value, args)
+    fn interpret_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        span: &Span,
+    ) -> Result<Value, RuntimeError> {
+        let (callee_value, receiver) = self.resolve_callee(callee)?;
+        if !matches!(callee_value, Value::ToolRef { .. }) {
+            return Err(RuntimeError::NotCallable(format!(
+                "{} is {}",
+                Self::describe_callee(callee),
+                callee_value.type_name()
+            )));
+        }
+        self.interpret_call_value(callee_value, receiver, args, span)
+    }
+
+    // the tool to call, plus the object to pass as `self` for a method called on an
+    // instance. A callee that names nothing is an undefined tool, whether it is a bare name
+    // or a property; any other failure comes from evaluating the expression around it
+    fn resolve_callee(&mut self, callee: &Expr) -> Result<(Value, Option<Value>), RuntimeError> {
+        let undefined = || RuntimeError::UndefinedTool(Self::describe_callee(callee));
+        match &callee.inner {
+            ExprKind::Identifier(name) => {
+                let value = self
+                    .interpret_expression(callee)
+                    .map_err(|error| match error {
+                        RuntimeError::UndefinedVariable(missing) if missing == *name => undefined(),
+                        other => other,
+                    })?;
+                Ok((value, None))
+            }
+            ExprKind::Property { object, property } => {
+                let object_value = self.interpret_expression(object)?;
+                match &object_value {
+                    // fields win over methods of the same name
+                    Value::Object {
+                        type_name,
+                        fields,
+                        members,
+                    } if !fields.contains_key(property) => {
+                        let method = Self::struct_method(type_name, members, property)
+                            .ok_or_else(undefined)?;
+                        let takes_self = matches!(
+                            &method,
+                            Value::ToolRef { params, .. }
+                                if params.first().is_some_and(|p| p.name == "self")
+                        );
+                        Ok((method, takes_self.then_some(object_value)))
+                    }
+                    // called on the type itself, every parameter is passed explicitly
+                    Value::TypeRef(TypeDef::Struct { name, members }) => {
+                        let method =
+                            Self::struct_method(name, members, property).ok_or_else(undefined)?;
+                        Ok((method, None))
+                    }
+                    _ => {
+                        let value =
+                            object_value
+                                .get_property(property)
+                                .map_err(|error| match error {
+                                    RuntimeError::FieldNotFound(_) => undefined(),
+                                    other => other,
+                                })?;
+                        Ok((value, None))
+                    }
+                }
+            }
+            _ => Ok((self.interpret_expression(callee)?, None)),
+        }
+    }
+
+    // a tool declared inside a struct, named `Struct.tool` in traces and errors
+    fn struct_method(name: &str, members: &[StructMember], method: &str) -> Option<Value> {
+        members.iter().find_map(|member| match member {
+            StructMember::ToolDecl {
+                name: tool,
+                params,
+                return_type,
+                body,
+            } if tool == method => Some(Value::ToolRef {
+                name: format!("{}.{}", name, tool),
+                params: params.clone(),
+                return_type: return_type.clone(),
+                body: body.clone(),
+            }),
+            _ => None,
+        })
+    }
+
+    fn describe_callee(callee: &Expr) -> String {
+        match &callee.inner {
+            ExprKind::Identifier(name) => name.clone(),
+            ExprKind::Property { object, property } => {
+                format!("{}.{}", Self::describe_callee(object), property)
+            }
+            ExprKind::Call { callee, .. } => format!("{}(...)", Self::describe_callee(callee)),
+            _ => "expression".to_string(),
+        }
     }
 
+    // `receiver` goes first, ahead of the arguments written at the call site
     fn interpret_call_value(
         &mut self,
         callee_value: Value,
+        receiver: Option<Value>,
         args: &[Expr],
+        span: &Span,
+    ) -> Result<Value, RuntimeError> {
+        let mut arg_values = self.interpret_items(args)?;
+        // a spread shifts every later position, so then no argument is matched to its expression
+        self.division_args = if args
+            .iter()
+            .any(|arg| matches!(arg.inner, ExprKind::Spread(_)))
+        {
+            Vec::new()
+        } else {
+            receiver
+                .iter()
+                .map(|_| false)
+                .chain(args.iter().map(Self::is_division))
+                .collect()
+        };
+        if let Some(receiver) = receiver {
+            arg_values.insert(0, receiver);
+        }
+        self.call_value(callee_value, arg_values, span)
+    }
+
+    pub(crate) fn is_division(expr: &Expr) -> bool {
+        matches!(
+            expr.inner,
+            ExprKind::BinaryOp {
+                op: TokenKind::Divide,
+                ..
+            }
+        )
+    }
+
+    // call arguments or list items in order, with each `...xs` replaced by the items of xs
+    fn interpret_items(&mut self, exprs: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            match &expr.inner {
+                ExprKind::Spread(list) => {
+                    let list = self.interpret_expression(list)?;
+                    values.extend(Self::expect_list(list)?);
+                }
+                _ => values.push(self.interpret_expression(expr)?),
+            }
+        }
+        Ok(values)
+    }
+
+    pub(crate) fn call_value(
+        &mut self,
+        callee_value: Value,
+        arg_values: Vec<Value>,
+        span: &Span,
     ) -> Result<Value, RuntimeError> {
+        // only meant for this call, never for one made while running it
+        let divisions = std::mem::take(&mut self.division_args);
         match callee_value {
-            Value::ToolRef { name, params, body } => {
+            Value::ToolRef {
+                name,
+                params,
+                return_type,
+                body,
+            } => {
                 if body.is_empty() {
-                    return self.call_builtin(&name, args);
+                    return self.call_builtin(&name, arg_values, span);
                 }
 
-                if args.len() != params.len() {
-                    return Err(RuntimeError::InvalidArguments(format!(
-                        "Expected {} arguments, got {}",
-                        params.len(),
-                        args.len()
-                    )));
-                }
+                Self::check_arguments(&self.env, &name, &params, &arg_values, &divisions)?;
 
-                let mut arg_values = Vec::new();
-                for arg in args {
-                    arg_values.push(self.interpret_expression(arg)?);
+                if self.call_stack.len() >= self.max_call_depth {
+                    self.capture_error_trace();
+                    return Err(RuntimeError::Custom(format!(
+                        "stack overflow: max call depth of {} exceeded calling '{}'",
+                        self.max_call_depth, name
+                    )));
                 }
 
+                self.call_stack.push(CallFrame {
+                    name: name.clone(),
+                    span: span.clone(),
+                    file: None,
+                });
                 self.env.push_scope();
                 self.env.enter_tool();
 
-                for (param, arg_value) in params.iter().zip(arg_values.iter()) {
-                    self.env.set(&param.name, arg_value.clone());
+                let provided = arg_values.len();
+                for (param, arg_value) in params.iter().zip(arg_values) {
+                    self.env.set(&param.name, arg_value);
                 }
 
-                let mut result = Value::Null;
-                for stmt in &body {
-                    match self.interpret_statement(stmt)? {
-                        ControlFlow::Return(value) => {
-                            result = value;
-                            break;
-                        }
-                        ControlFlow::Break => return Err(RuntimeError::BreakOutsideLoop),
-                        ControlFlow::Continue => return Err(RuntimeError::ContinueOutsideLoop),
-                        ControlFlow::None => {}
-                    }
-                }
+                let result = match self.bind_default_params(&params[provided..]) {
+                    Ok(()) => self.interpret_tool_body(&body),
+                    Err(error) => Err(error),
+                };
+                let result = match (result, &return_type) {
+                    (Ok(value), Some(ty)) => Self::check_return_type(
+                        &self.env,
+                        &name,
+                        ty,
+                        value,
+                        std::mem::take(&mut self.returned_division),
+                    ),
+                    (result, _) => result,
+                };
 
                 self.env.exit_tool();
                 self.env.pop_scope();
-                Ok(result)
+                if result.is_err() && self.error_trace.is_empty() {
+                    self.capture_error_trace();
+                }
+                self.call_stack.pop();
+                result
             }
-            _ => Err(RuntimeError::NotCallable),
+            other => Err(RuntimeError::NotCallable(other.type_name().to_string())),
         }
     }
 
-    fn call_builtin(&mut self, name: &str, args: &[Expr]) -> Result<Value, RuntimeError> {
-        match name {
-            "print" => {
-                for arg in args {
-                    let value = self.interpret_expression(arg)?;
-                    print!("{} ", value);
-                }
-                println!();
-                Ok(Value::Null)
+    // arity (allowing for defaults) and the declared type of each supplied argument;
+    // `divisions` marks the arguments written as `a / b`, when the call site is known
+    pub(crate) fn check_arguments(
+        env: &Environment,
+        name: &str,
+        params: &[ParamDecl],
+        arg_values: &[Value],
+        divisions: &[bool],
+    ) -> Result<(), RuntimeError> {
+        let required = params.iter().filter(|p| p.default.is_none()).count();
+        if arg_values.len() < required || arg_values.len() > params.len() {
+            let expected = if required == params.len() {
+                params.len().to_string()
+            } else {
+                format!("{} to {}", required, params.len())
+            };
+            return Err(RuntimeError::InvalidArguments(format!(
+                "Tool '{}' expected {} arguments, got {}",
+                name,
+                expected,
+                arg_values.len()
+            )));
+        }
+
+        for (position, (param, arg_value)) in params.iter().zip(arg_values).enumerate() {
+            if !arg_value.matches_type(&param.ty.inner, &|ty| env.declares_type(ty)) {
+                let expected = format!(
+                    "{} for parameter '{}' (argument {}) of '{}'",
+                    param.ty.inner,
+                    param.name,
+                    position + 1,
+                    name
+                );
+                let from_division = divisions.get(position).copied().unwrap_or(false);
+                return Err(Self::type_mismatch(
+                    expected,
+                    arg_value,
+                    &param.ty,
+                    from_division,
+                ));
             }
-            "panic" => {
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_return_type(
+        env: &Environment,
+        name: &str,
+        ty: &TypeExpr,
+        value: Value,
+        from_division: bool,
+    ) -> Result<Value, RuntimeError> {
+        if value.matches_type(&ty.inner, &|name| env.declares_type(name)) {
+            return Ok(value);
+        }
+        if matches!(value, Value::Null) {
+            return Err(RuntimeError::Custom(format!(
+                "tool {} declared -> {} but returned null",
+                name, ty.inner
+            )));
+        }
+        let expected = format!("{} as the return type of '{}'", ty.inner, name);
+        Err(Self::type_mismatch(expected, &value, ty, from_division))
+    }
+
+    // a Float from `/` reaching an Int slot is most likely division that used to truncate
+    fn type_mismatch(
+        expected: String,
+        value: &Value,
+        ty: &TypeExpr,
+        from_division: bool,
+    ) -> RuntimeError {
+        if from_division && matches!(value, Value::Float(_)) && ty.inner.base_name() == "Int" {
+            RuntimeError::FloatDivision { expected }
+        } else {
+            RuntimeError::TypeMismatch {
+                expected,
+                actual: value.type_label(),
+            }
+        }
+    }
+
+    // defaults are evaluated in the callee's scope so they can refer to earlier parameters
+    fn bind_default_params(&mut self, params: &[ParamDecl]) -> Result<(), RuntimeError> {
+        for param in params {
+            if let Some(default) = &param.default {
+                let value = self.interpret_expression(default)?;
+                self.env.set(&param.name, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn interpret_tool_body(&mut self, body: &[Stmt]) -> Result<Value, RuntimeError> {
+        match self.interpret_block(body)? {
+            ControlFlow::Return(value) => Ok(value),
+            ControlFlow::Break(_) => Err(RuntimeError::BreakOutsideLoop),
+            ControlFlow::Continue => Err(RuntimeError::ContinueOutsideLoop),
+            ControlFlow::None => Ok(Value::Null),
+        }
+    }
+
+    pub(crate) fn call_builtin(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        span: &Span,
+    ) -> Result<Value, RuntimeError> {
+        match name {
+            "print" => {
+                let write_error =
+                    |e: io::Error| RuntimeError::Custom(format!("Failed to write output: {}", e));
+                let line = args
+                    .iter()
+                    .map(Value::to_display_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(self.output, "{}", line).map_err(write_error)?;
+                Ok(Value::Null)
+            }
+            "panic" => {
                 let message = if args.is_empty() {
                     "panic".to_string()
                 } else {
-                    let msg_value = self.interpret_expression(&args[0])?;
-                    msg_value.to_string()
+                    args[0].to_display_string()
                 };
                 Err(RuntimeError::Custom(message))
             }
-            "list" => {
-                let mut items = Vec::new();
-                for arg in args {
-                    items.push(self.interpret_expression(arg)?);
-                }
-                Ok(Value::List(items))
-            }
+            "list" => Ok(Value::List(args)),
             "cons" => {
                 if args.len() != 2 {
                     return Err(RuntimeError::InvalidArguments(
                         "cons requires 2 arguments".to_string(),
                     ));
                 }
-                let head = self.interpret_expression(&args[0])?;
-                let tail = self.interpret_expression(&args[1])?;
+                let head = args[0].clone();
+                let tail = args[1].clone();
 
                 match tail {
                     Value::List(mut items) => {
@@ -506,27 +1372,51 @@ impl Interpreter {
                     _ => Ok(Value::List(vec![head, tail])),
                 }
             }
-            "get" => {
+            "pair" => {
                 if args.len() != 2 {
                     return Err(RuntimeError::InvalidArguments(
-                        "get requires 2 arguments".to_string(),
+                        "pair requires 2 arguments".to_string(),
                     ));
                 }
-                let list_val = self.interpret_expression(&args[0])?;
-                let index_val = self.interpret_expression(&args[1])?;
+                Ok(Value::List(args))
+            }
+            "object" => {
+                if !args.len().is_multiple_of(2) {
+                    return Err(RuntimeError::InvalidArguments(
+                        "object requires alternating keys and values".to_string(),
+                    ));
+                }
+                let mut entries = IndexMap::new();
+                let mut args = args.into_iter();
+                while let (Some(key), Some(value)) = (args.next(), args.next()) {
+                    entries.insert(MapKey::from_value(key)?, value);
+                }
+                Ok(Value::Map(entries))
+            }
+            // negative indices count from the end; a miss is Null for get (as it always
+            // was) and the caller's default for get_or, so a stored Null can be told apart
+            "get" | "get_or" => {
+                let expected = if name == "get" { 2 } else { 3 };
+                if args.len() != expected {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires {} arguments",
+                        name, expected
+                    )));
+                }
+                let mut args = args.into_iter();
+                let (list_val, index_val) = (args.next().unwrap(), args.next().unwrap());
+                let fallback = args.next().unwrap_or(Value::Null);
 
                 match (list_val, index_val) {
                     (Value::List(items), Value::Int(index)) => {
-                        let idx = index as usize;
-                        if idx < items.len() {
-                            Ok(items[idx].clone())
-                        } else {
-                            Ok(Value::Null)
+                        match Self::offset_index(index, items.len()) {
+                            Some(idx) => Ok(items[idx].clone()),
+                            None => Ok(fallback),
                         }
                     }
-                    _ => Err(RuntimeError::TypeMismatch {
+                    (list, index) => Err(RuntimeError::TypeMismatch {
                         expected: "List and Int".to_string(),
-                        actual: "other".to_string(),
+                        actual: format!("{} and {}", list.type_name(), index.type_name()),
                     }),
                 }
             }
@@ -536,15 +1426,19 @@ impl Interpreter {
                         "lookup requires 2 arguments".to_string(),
                     ));
                 }
-                let obj_val = self.interpret_expression(&args[0])?;
-                let key_val = self.interpret_expression(&args[1])?;
+                let obj_val = args[0].clone();
+                let key_val = args[1].clone();
 
                 match (obj_val, key_val) {
                     (Value::Object { fields, .. }, Value::String(key)) => {
                         Ok(fields.get(&key).cloned().unwrap_or(Value::Null))
                     }
+                    (Value::Map(entries), key) => {
+                        let key = MapKey::from_value(key)?;
+                        Ok(entries.get(&key).cloned().unwrap_or(Value::Null))
+                    }
                     _ => Err(RuntimeError::TypeMismatch {
-                        expected: "Object and String".to_string(),
+                        expected: "Object and String, or Map and key".to_string(),
                         actual: "other".to_string(),
                     }),
                 }
@@ -555,7 +1449,7 @@ impl Interpreter {
                         "int requires 1 argument".to_string(),
                     ));
                 }
-                let val = self.interpret_expression(&args[0])?;
+                let val = args[0].clone();
                 val.to_int().map(Value::Int)
             }
             "float" => {
@@ -564,16 +1458,33 @@ impl Interpreter {
                         "float requires 1 argument".to_string(),
                     ));
                 }
-                let val = self.interpret_expression(&args[0])?;
+                let val = args[0].clone();
                 val.to_float().map(Value::Float)
             }
+            "parse_int" | "parse_float" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 1 argument",
+                        name
+                    )));
+                }
+                // unlike int()/float(), a string that doesn't parse gives null
+                let text = Self::expect_string(args[0].clone())?;
+                let text = text.trim();
+                let parsed = if name == "parse_int" {
+                    text.parse::<i64>().ok().map(Value::Int)
+                } else {
+                    text.parse::<f64>().ok().map(Value::Float)
+                };
+                Ok(parsed.unwrap_or(Value::Null))
+            }
             "bool" => {
                 if args.len() != 1 {
                     return Err(RuntimeError::InvalidArguments(
                         "bool requires 1 argument".to_string(),
                     ));
                 }
-                let val = self.interpret_expression(&args[0])?;
+                let val = args[0].clone();
                 Ok(Value::Bool(val.to_bool()))
             }
             "str" => {
@@ -582,38 +1493,822 @@ impl Interpreter {
                         "str requires 1 argument".to_string(),
                     ));
                 }
-                let val = self.interpret_expression(&args[0])?;
-                Ok(Value::String(val.as_string()))
+                let val = args[0].clone();
+                Ok(Value::String(val.to_display_string()))
+            }
+            // the quoted form `print` shows for strings nested in lists and objects
+            "repr" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "repr requires 1 argument".to_string(),
+                    ));
+                }
+                Ok(Value::String(args[0].to_string()))
+            }
+            // values hold no shared references, so every copy is already a deep one;
+            // clone just spells that out where aliasing would otherwise be a question
+            "clone" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "clone requires 1 argument".to_string(),
+                    ));
+                }
+                Ok(args[0].clone())
+            }
+            "abs" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "abs requires 1 argument".to_string(),
+                    ));
+                }
+                match args[0].clone() {
+                    Value::Int(n) => Self::checked_int(n.checked_abs()),
+                    Value::Float(f) => Ok(Value::Float(f.abs())),
+                    other => Err(RuntimeError::TypeMismatch {
+                        expected: "numeric".to_string(),
+                        actual: other.type_name().to_string(),
+                    }),
+                }
+            }
+            "min" | "max" => {
+                if args.len() < 2 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires at least 2 arguments",
+                        name
+                    )));
+                }
+                let mut best: Option<Value> = None;
+                for val in args {
+                    let num = match val {
+                        Value::Int(n) => n as f64,
+                        Value::Float(f) => f,
+                        _ => {
+                            return Err(RuntimeError::TypeMismatch {
+                                expected: "numeric".to_string(),
+                                actual: val.type_name().to_string(),
+                            });
+                        }
+                    };
+                    let replace = match &best {
+                        None => true,
+                        Some(current) => {
+                            let current = current.to_float()?;
+                            if name == "min" {
+                                num < current
+                            } else {
+                                num > current
+                            }
+                        }
+                    };
+                    if replace {
+                        best = Some(val);
+                    }
+                }
+                Ok(best.unwrap_or(Value::Null))
+            }
+            "floor" | "ceil" | "round" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 1 argument",
+                        name
+                    )));
+                }
+                match args[0].clone() {
+                    Value::Int(n) => Ok(Value::Int(n)),
+                    Value::Float(f) => Ok(Value::Float(match name {
+                        "floor" => f.floor(),
+                        "ceil" => f.ceil(),
+                        _ => f.round(),
+                    })),
+                    other => Err(RuntimeError::TypeMismatch {
+                        expected: "numeric".to_string(),
+                        actual: other.type_name().to_string(),
+                    }),
+                }
+            }
+            "sqrt" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "sqrt requires 1 argument".to_string(),
+                    ));
+                }
+                let val = args[0].clone();
+                let num = match val {
+                    Value::Int(n) => n as f64,
+                    Value::Float(f) => f,
+                    _ => {
+                        return Err(RuntimeError::TypeMismatch {
+                            expected: "numeric".to_string(),
+                            actual: val.type_name().to_string(),
+                        });
+                    }
+                };
+                if num < 0.0 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "sqrt of negative number {}",
+                        val
+                    )));
+                }
+                Ok(Value::Float(num.sqrt()))
+            }
+            "sign" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "sign requires 1 argument".to_string(),
+                    ));
+                }
+                match args[0].clone() {
+                    Value::Int(n) => Ok(Value::Int(n.signum())),
+                    // NaN has no sign, so it lands on 0 along with both zeros
+                    Value::Float(f) if f > 0.0 => Ok(Value::Int(1)),
+                    Value::Float(f) if f < 0.0 => Ok(Value::Int(-1)),
+                    Value::Float(_) => Ok(Value::Int(0)),
+                    other => Err(RuntimeError::TypeMismatch {
+                        expected: "numeric".to_string(),
+                        actual: other.type_name().to_string(),
+                    }),
+                }
+            }
+            "gcd" | "lcm" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 2 arguments",
+                        name
+                    )));
+                }
+                let (a, b) = match (&args[0], &args[1]) {
+                    (Value::Int(a), Value::Int(b)) => (*a, *b),
+                    (a, b) => {
+                        return Err(RuntimeError::TypeMismatch {
+                            expected: "Int and Int".to_string(),
+                            actual: format!("{} and {}", a.type_name(), b.type_name()),
+                        });
+                    }
+                };
+                // unsigned so gcd(i64::MIN, 0) can be computed before the range check
+                let (mut x, mut y) = (a.unsigned_abs(), b.unsigned_abs());
+                while y != 0 {
+                    (x, y) = (y, x % y);
+                }
+                let result = if name == "gcd" {
+                    Some(x)
+                } else {
+                    // x is 0 only for lcm(0, 0), which is 0
+                    a.unsigned_abs()
+                        .checked_div(x)
+                        .map_or(Some(0), |n| n.checked_mul(b.unsigned_abs()))
+                };
+                Self::checked_int(result.and_then(|n| i64::try_from(n).ok()))
+            }
+            "range" => {
+                if args.len() != 2 && args.len() != 3 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "range requires 2 or 3 arguments".to_string(),
+                    ));
+                }
+                let mut bounds = Vec::new();
+                for arg in args {
+                    match arg {
+                        Value::Int(n) => bounds.push(n),
+                        other => {
+                            return Err(RuntimeError::TypeMismatch {
+                                expected: "Int".to_string(),
+                                actual: other.type_name().to_string(),
+                            });
+                        }
+                    }
+                }
+                let (start, end) = (bounds[0], bounds[1]);
+                let step = bounds.get(2).copied().unwrap_or(1);
+                if step == 0 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "range step cannot be zero".to_string(),
+                    ));
+                }
+
+                // half-open like `start..end`: counts towards `end` but never includes it
+                let mut items = Vec::new();
+                let mut current = start;
+                while (step > 0 && current < end) || (step < 0 && current > end) {
+                    items.push(Value::Int(current));
+                    match current.checked_add(step) {
+                        Some(next) => current = next,
+                        None => break,
+                    }
+                }
+                Ok(Value::List(items))
+            }
+            "push" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "push requires 2 arguments".to_string(),
+                    ));
+                }
+                let mut items = Self::expect_list(args[0].clone())?;
+                items.push(args[1].clone());
+                Ok(Value::List(items))
+            }
+            "pop" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "pop requires 1 argument".to_string(),
+                    ));
+                }
+                let mut items = Self::expect_list(args[0].clone())?;
+                match items.pop() {
+                    // [remaining, popped] so callers keep both halves
+                    Some(last) => Ok(Value::List(vec![Value::List(items), last])),
+                    None => Err(RuntimeError::InvalidArguments(
+                        "pop from empty list".to_string(),
+                    )),
+                }
+            }
+            "insert" => {
+                if args.len() != 3 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "insert requires 3 arguments".to_string(),
+                    ));
+                }
+                let mut items = Self::expect_list(args[0].clone())?;
+                let index = args[1].clone();
+                let value = args[2].clone();
+                let idx = Self::list_index(&index, items.len() + 1)?;
+                items.insert(idx, value);
+                Ok(Value::List(items))
+            }
+            "remove" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "remove requires 2 arguments".to_string(),
+                    ));
+                }
+                let mut items = Self::expect_list(args[0].clone())?;
+                let index = args[1].clone();
+                let idx = Self::list_index(&index, items.len())?;
+                items.remove(idx);
+                Ok(Value::List(items))
+            }
+            "concat" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "concat requires 2 arguments".to_string(),
+                    ));
+                }
+                let mut items = Self::expect_list(args[0].clone())?;
+                items.extend(Self::expect_list(args[1].clone())?);
+                Ok(Value::List(items))
+            }
+            // both return a new list; the argument is never reordered in place
+            "sort" | "sorted" => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 1 or 2 arguments",
+                        name
+                    )));
+                }
+                let items = Self::expect_list(args[0].clone())?;
+                let comparator = args.get(1).cloned();
+                let sorted = Self::merge_sort(items, &mut |a, b| match &comparator {
+                    Some(cmp) => {
+                        match self.call_value(cmp.clone(), vec![a.clone(), b.clone()], span)? {
+                            Value::Int(n) => Ok(n.cmp(&0)),
+                            other => Err(RuntimeError::TypeMismatch {
+                                expected: "Int from sort comparator".to_string(),
+                                actual: other.type_name().to_string(),
+                            }),
+                        }
+                    }
+                    None => Self::value_ordering(a, b).ok_or_else(|| RuntimeError::TypeMismatch {
+                        expected: "comparable values to sort".to_string(),
+                        actual: format!("{} and {}", a.type_name(), b.type_name()),
+                    }),
+                })?;
+                Ok(Value::List(sorted))
+            }
+            "reverse" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "reverse requires 1 argument".to_string(),
+                    ));
+                }
+                let mut items = Self::expect_list(args[0].clone())?;
+                items.reverse();
+                Ok(Value::List(items))
+            }
+            "enumerate" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "enumerate requires 1 argument".to_string(),
+                    ));
+                }
+                let items = Self::expect_list(args[0].clone())?;
+                Ok(Value::List(
+                    items
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, item)| Value::List(vec![Value::Int(i as i64), item]))
+                        .collect(),
+                ))
+            }
+            "now" => {
+                if !args.is_empty() {
+                    return Err(RuntimeError::InvalidArguments(
+                        "now takes no arguments".to_string(),
+                    ));
+                }
+                // milliseconds since the Unix epoch; a clock set before 1970 reads as 0
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as i64);
+                Ok(Value::Int(millis))
+            }
+            "read_file" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "read_file requires 1 argument".to_string(),
+                    ));
+                }
+                self.ensure_io_allowed("read_file")?;
+                let path = Self::expect_string(args[0].clone())?;
+                fs::read_to_string(&path)
+                    .map(Value::String)
+                    .map_err(|e| RuntimeError::Custom(format!("Failed to read '{}': {}", path, e)))
+            }
+            "write_file" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "write_file requires 2 arguments".to_string(),
+                    ));
+                }
+                self.ensure_io_allowed("write_file")?;
+                let path = Self::expect_string(args[0].clone())?;
+                let contents = Self::expect_string(args[1].clone())?;
+                fs::write(&path, contents)
+                    .map(|_| Value::Null)
+                    .map_err(|e| RuntimeError::Custom(format!("Failed to write '{}': {}", path, e)))
+            }
+            "div" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "div requires 2 arguments".to_string(),
+                    ));
+                }
+                self.floor_divide_values(args[0].clone(), args[1].clone())
+            }
+            "divmod" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "divmod requires 2 arguments".to_string(),
+                    ));
+                }
+                match (&args[0], &args[1]) {
+                    (Value::Int(a), Value::Int(b)) => {
+                        let quotient = self.floor_divide_values(Value::Int(*a), Value::Int(*b))?;
+                        let Value::Int(q) = quotient else {
+                            unreachable!("integer floor division yields an Int")
+                        };
+                        // remainder that pairs with the floored quotient: a == q * b + r
+                        let remainder =
+                            q.checked_mul(*b).and_then(|product| a.checked_sub(product));
+                        Ok(Value::List(vec![
+                            Value::Int(q),
+                            Self::checked_int(remainder)?,
+                        ]))
+                    }
+                    (a, b) => Err(RuntimeError::TypeMismatch {
+                        expected: "Int and Int".to_string(),
+                        actual: format!("{} and {}", a.type_name(), b.type_name()),
+                    }),
+                }
+            }
+            "zip" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "zip requires 2 arguments".to_string(),
+                    ));
+                }
+                let left = Self::expect_list(args[0].clone())?;
+                let right = Self::expect_list(args[1].clone())?;
+                Ok(Value::List(
+                    left.into_iter()
+                        .zip(right)
+                        .map(|(a, b)| Value::List(vec![a, b]))
+                        .collect(),
+                ))
+            }
+            "has_field" | "remove_field" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 2 arguments",
+                        name
+                    )));
+                }
+                let field = Self::expect_string(args[1].clone())?;
+                if name == "has_field" {
+                    Ok(Value::Bool(args[0].has_field(&field)?))
+                } else {
+                    args[0].remove_field(&field)
+                }
+            }
+            "contains" | "index_of" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 2 arguments",
+                        name
+                    )));
+                }
+                let index = match (&args[0], &args[1]) {
+                    (Value::List(items), needle) => {
+                        let mut found = None;
+                        for (i, item) in items.iter().enumerate() {
+                            if self.values_equal(item, needle)? {
+                                found = Some(i);
+                                break;
+                            }
+                        }
+                        found
+                    }
+                    (Value::String(haystack), Value::String(needle)) => haystack
+                        .find(needle.as_str())
+                        .map(|byte_idx| haystack[..byte_idx].chars().count()),
+                    (Value::String(haystack), Value::Char(needle)) => {
+                        haystack.chars().position(|c| c == *needle)
+                    }
+                    (collection, needle) => {
+                        return Err(RuntimeError::TypeMismatch {
+                            expected: "List, or String and String/Char".to_string(),
+                            actual: format!(
+                                "{} and {}",
+                                collection.type_name(),
+                                needle.type_name()
+                            ),
+                        });
+                    }
+                };
+                if name == "contains" {
+                    Ok(Value::Bool(index.is_some()))
+                } else {
+                    Ok(Value::Int(index.map_or(-1, |i| i as i64)))
+                }
+            }
+            "slice" => {
+                if args.len() != 3 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "slice requires 3 arguments".to_string(),
+                    ));
+                }
+                let (start, end) = match (&args[1], &args[2]) {
+                    (Value::Int(start), Value::Int(end)) => (*start, *end),
+                    (start, end) => {
+                        return Err(RuntimeError::TypeMismatch {
+                            expected: "Int and Int".to_string(),
+                            actual: format!("{} and {}", start.type_name(), end.type_name()),
+                        });
+                    }
+                };
+                match &args[0] {
+                    Value::List(items) => {
+                        let (from, to) = Self::slice_bounds(start, end, items.len());
+                        Ok(Value::List(items[from..to].to_vec()))
+                    }
+                    Value::String(s) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        let (from, to) = Self::slice_bounds(start, end, chars.len());
+                        Ok(Value::String(chars[from..to].iter().collect()))
+                    }
+                    other => Err(RuntimeError::TypeMismatch {
+                        expected: "List or String".to_string(),
+                        actual: other.type_name().to_string(),
+                    }),
+                }
+            }
+            "trim" | "trim_start" | "trim_end" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 1 argument",
+                        name
+                    )));
+                }
+                let s = Self::expect_string(args[0].clone())?;
+                let trimmed = match name {
+                    "trim_start" => s.trim_start(),
+                    "trim_end" => s.trim_end(),
+                    _ => s.trim(),
+                };
+                Ok(Value::String(trimmed.to_string()))
+            }
+            "lines" | "split_lines" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 1 argument",
+                        name
+                    )));
+                }
+                let s = Self::expect_string(args[0].clone())?;
+                let mut parts: Vec<Value> = s
+                    .split('\n')
+                    .map(|line| Value::String(line.to_string()))
+                    .collect();
+                if matches!(parts.last(), Some(Value::String(last)) if last.is_empty()) {
+                    parts.pop();
+                }
+                Ok(Value::List(parts))
+            }
+            "upper" | "lower" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 1 argument",
+                        name
+                    )));
+                }
+                let s = Self::expect_string(args[0].clone())?;
+                Ok(Value::String(if name == "upper" {
+                    s.to_uppercase()
+                } else {
+                    s.to_lowercase()
+                }))
+            }
+            "replace" => {
+                if args.len() != 3 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "replace requires 3 arguments".to_string(),
+                    ));
+                }
+                let mut args = args.into_iter();
+                let s = Self::expect_string(args.next().unwrap())?;
+                let from = Self::expect_string(args.next().unwrap())?;
+                let to = Self::expect_string(args.next().unwrap())?;
+                // an empty pattern would match between every char, so leave the string alone
+                if from.is_empty() {
+                    return Ok(Value::String(s));
+                }
+                Ok(Value::String(s.replace(&from, &to)))
+            }
+            "starts_with" | "ends_with" => {
+                if args.len() != 2 {
+                    return Err(RuntimeError::InvalidArguments(format!(
+                        "{} requires 2 arguments",
+                        name
+                    )));
+                }
+                let mut args = args.into_iter();
+                let s = Self::expect_string(args.next().unwrap())?;
+                let affix = Self::expect_string(args.next().unwrap())?;
+                Ok(Value::Bool(if name == "starts_with" {
+                    s.starts_with(&affix)
+                } else {
+                    s.ends_with(&affix)
+                }))
+            }
+            "memo" => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::InvalidArguments(
+                        "memo requires 1 argument".to_string(),
+                    ));
+                }
+                let Value::ToolRef {
+                    name: tool_name,
+                    params,
+                    return_type,
+                    ..
+                } = &args[0]
+                else {
+                    return Err(RuntimeError::TypeMismatch {
+                        expected: "Tool".to_string(),
+                        actual: args[0].type_name().to_string(),
+                    });
+                };
+                // an empty body routes calls back here, like any builtin
+                let wrapper = Value::ToolRef {
+                    name: format!("{}#memo{}", tool_name, self.memos.len()),
+                    params: params.clone(),
+                    return_type: return_type.clone(),
+                    body: vec![],
+                };
+                self.memos.push(Memo {
+                    tool: args[0].clone(),
+                    cache: HashMap::new(),
+                });
+                Ok(wrapper)
+            }
+            _ => match name
+                .rsplit_once("#memo")
+                .and_then(|(_, id)| id.parse::<usize>().ok())
+            {
+                Some(id) if id < self.memos.len() => self.call_memoized(id, args, span),
+                _ => Err(RuntimeError::UndefinedTool(name.to_string())),
+            },
+        }
+    }
+
+    fn call_memoized(
+        &mut self,
+        id: usize,
+        args: Vec<Value>,
+        span: &Span,
+    ) -> Result<Value, RuntimeError> {
+        // arguments without a hashable form (tools, types, modules) skip the cache
+        let key: Option<Vec<MemoKey>> = args.iter().map(MemoKey::from_value).collect();
+        if let Some(key) = &key
+            && let Some(cached) = self.memos[id].cache.get(key)
+        {
+            return Ok(cached.clone());
+        }
+        let tool = self.memos[id].tool.clone();
+        let result = self.call_value(tool, args, span)?;
+        if let Some(key) = key {
+            self.memos[id].cache.insert(key, result.clone());
+        }
+        Ok(result)
+    }
+
+    // position of `index` in a list of `len` items, counting back from the end when negative
+    fn offset_index(index: i64, len: usize) -> Option<usize> {
+        let index = if index < 0 {
+            index.checked_add(len as i64)?
+        } else {
+            index
+        };
+        usize::try_from(index).ok().filter(|&i| i < len)
+    }
+
+    // python-style slice bounds: negatives count from the end, everything clamps to the length
+    fn slice_bounds(start: i64, end: i64, len: usize) -> (usize, usize) {
+        let clamp = |i: i64| {
+            let i = if i < 0 { i + len as i64 } else { i };
+            i.clamp(0, len as i64) as usize
+        };
+        let (from, to) = (clamp(start), clamp(end));
+        if from > to { (from, from) } else { (from, to) }
+    }
+
+    fn expect_list(value: Value) -> Result<Vec<Value>, RuntimeError> {
+        match value {
+            Value::List(items) => Ok(items),
+            other => Err(RuntimeError::TypeMismatch {
+                expected: "List".to_string(),
+                actual: other.type_name().to_string(),
+            }),
+        }
+    }
+
+    // every name is bound as if by its own `let` or plain assignment
+    fn destructure(
+        &mut self,
+        pattern: &DestructurePattern,
+        value: Value,
+        declare: bool,
+    ) -> Result<(), RuntimeError> {
+        match pattern {
+            DestructurePattern::List { names, rest } => {
+                let mut items = Self::expect_list(value)?;
+                let fits = match rest {
+                    Some(_) => items.len() >= names.len(),
+                    None => items.len() == names.len(),
+                };
+                if !fits {
+                    return Err(RuntimeError::Custom(format!(
+                        "cannot destructure a list of {} items into {} {} names",
+                        items.len(),
+                        if rest.is_some() {
+                            "at least"
+                        } else {
+                            "exactly"
+                        },
+                        names.len()
+                    )));
+                }
+                let tail = items.split_off(names.len());
+                for (name, item) in names.iter().zip(items) {
+                    self.bind(name, item, declare)?;
+                }
+                if let Some(rest) = rest {
+                    self.bind(rest, Value::List(tail), declare)?;
+                }
             }
-            _ => Err(RuntimeError::UndefinedTool(name.to_string())),
+            DestructurePattern::Object { fields } => {
+                let Value::Object { fields: values, .. } = value else {
+                    return Err(RuntimeError::TypeMismatch {
+                        expected: "Object".to_string(),
+                        actual: value.type_name().to_string(),
+                    });
+                };
+                for field in fields {
+                    let item = values
+                        .get(field)
+                        .cloned()
+                        .ok_or_else(|| RuntimeError::FieldNotFound(field.clone()))?;
+                    self.bind(field, item, declare)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn bind(&mut self, name: &str, value: Value, declare: bool) -> Result<(), RuntimeError> {
+        if declare {
+            self.env.set(name, value);
+            Ok(())
+        } else {
+            self.env.assign(name, value)
+        }
+    }
+
+    // `xs = push(xs, v);` would copy the whole list on every call, which makes building
+    // a list in a loop quadratic; append to the stored list directly when that's safe
+    fn try_push_in_place(&mut self, target: &[String], value: &Expr) -> Result<bool, RuntimeError> {
+        let [name] = target else {
+            return Ok(false);
+        };
+        let ExprKind::Call { callee, args } = &value.inner else {
+            return Ok(false);
+        };
+        let [list, item] = args.as_slice() else {
+            return Ok(false);
+        };
+        if matches!(item.inner, ExprKind::Spread(_)) {
+            return Ok(false);
+        }
+        let is_push = matches!(&callee.inner, ExprKind::Identifier(tool) if tool == "push");
+        let is_self = matches!(&list.inner, ExprKind::Identifier(arg) if arg == name);
+        if !is_push || !is_self || !self.env.is_builtin_name("push") {
+            return Ok(false);
+        }
+        let item = self.interpret_expression(item)?;
+        if self.env.push_to_list(name, item.clone()) {
+            return Ok(true);
+        }
+        let list = self.env.get(name)?;
+        let pushed = self.call_builtin("push", vec![list, item], &value.span)?;
+        self.env.assign(name, pushed)?;
+        Ok(true)
+    }
+
+    fn ensure_io_allowed(&self, tool: &str) -> Result<(), RuntimeError> {
+        if self.allow_io {
+            Ok(())
+        } else {
+            Err(RuntimeError::Custom(format!(
+                "{}: file access is disabled in this interpreter",
+                tool
+            )))
+        }
+    }
+
+    fn expect_string(value: Value) -> Result<String, RuntimeError> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(RuntimeError::TypeMismatch {
+                expected: "String".to_string(),
+                actual: other.type_name().to_string(),
+            }),
+        }
+    }
+
+    fn list_index(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+        match index {
+            Value::Int(i) if *i >= 0 && (*i as usize) < len => Ok(*i as usize),
+            Value::Int(i) => Err(RuntimeError::InvalidArguments(format!(
+                "index {} out of bounds for length {}",
+                i, len
+            ))),
+            _ => Err(RuntimeError::TypeMismatch {
+                expected: "Int".to_string(),
+                actual: index.type_name().to_string(),
+            }),
         }
     }
 
     fn handle_load(
         &mut self,
-        path: &Vec<String>,
+        path: &[String],
         alias: &Option<String>,
         run: bool,
     ) -> Result<ControlFlow, RuntimeError> {
-        let module = self.module_cache.load_module(path, run)?;
+        self.module_cache.host = Some(self.module_host());
+        let loaded = self.module_cache.load_module(path, run);
+        if let Some(host) = self.module_cache.host.take() {
+            self.output = host.output;
+        }
+        let module = loaded?;
 
         if let Some(prefix) = alias {
-            let module_value = Value::Module {
-                tools: module.exports.tools.clone(),
-                structs: module.exports.structs.clone(),
-                templates: module.exports.templates.clone(),
-            };
-            self.env.set_path(&vec![prefix.clone()], module_value)?;
+            let module_value = Value::Module(Box::new(module.exports.clone()));
+            self.env.set(prefix, module_value);
         } else {
             for (_name, tool) in module.exports.tools {
-                self.env
-                    .define_tool(tool.name.clone(), tool.params, tool.body);
+                self.env.define_tool(
+                    tool.name.clone(),
+                    tool.params,
+                    tool.return_type,
+                    tool.body,
+                )?;
             }
             for (_name, struct_def) in module.exports.structs {
-                self.env.define_type(struct_def);
+                self.env.define_type(struct_def)?;
             }
             for (_name, template_def) in module.exports.templates {
-                self.env.define_type(template_def);
+                self.env.define_type(template_def)?;
             }
         }
 
@@ -622,7 +2317,7 @@ impl Interpreter {
 
     fn add_values(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a + b)),
+            (Value::Int(a), Value::Int(b)) => Self::checked_int(a.checked_add(b)),
             (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a + b)),
             (Value::Int(a), Value::Float(b)) => Ok(Value::Float(a as f64 + b)),
             (Value::Float(a), Value::Int(b)) => Ok(Value::Float(a + b as f64)),
@@ -636,7 +2331,7 @@ impl Interpreter {
 
     fn subtract_values(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a - b)),
+            (Value::Int(a), Value::Int(b)) => Self::checked_int(a.checked_sub(b)),
             (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a - b)),
             (Value::Int(a), Value::Float(b)) => Ok(Value::Float(a as f64 - b)),
             (Value::Float(a), Value::Int(b)) => Ok(Value::Float(a - b as f64)),
@@ -649,7 +2344,7 @@ impl Interpreter {
 
     fn multiply_values(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a * b)),
+            (Value::Int(a), Value::Int(b)) => Self::checked_int(a.checked_mul(b)),
             (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a * b)),
             (Value::Int(a), Value::Float(b)) => Ok(Value::Float(a as f64 * b)),
             (Value::Float(a), Value::Int(b)) => Ok(Value::Float(a * b as f64)),
@@ -662,11 +2357,12 @@ impl Interpreter {
 
     fn divide_values(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
+            // `/` always yields the real quotient; use `~/` for integer results
             (Value::Int(a), Value::Int(b)) => {
                 if b == 0 {
                     Err(RuntimeError::DivisionByZero)
                 } else {
-                    Ok(Value::Int(a / b))
+                    Ok(Value::Float(a as f64 / b as f64))
                 }
             }
             (Value::Float(a), Value::Float(b)) => {
@@ -697,13 +2393,56 @@ impl Interpreter {
         }
     }
 
+    // Int arithmetic fails the same way in debug and release builds instead of
+    // panicking or wrapping
+    fn checked_int(result: Option<i64>) -> Result<Value, RuntimeError> {
+        result
+            .map(Value::Int)
+            .ok_or_else(|| RuntimeError::Custom("integer overflow".to_string()))
+    }
+
+    fn floor_divide_values(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
+        match (left, right) {
+            (Value::Int(a), Value::Int(b)) => {
+                if b == 0 {
+                    return Err(RuntimeError::DivisionByZero);
+                }
+                // only i64::MIN ~/ -1 overflows, and then the remainder is 0
+                let quotient = Self::checked_int(a.checked_div(b))?;
+                // Rust truncates toward zero; step down when the signs differ
+                if a % b != 0 && ((a < 0) != (b < 0)) {
+                    Ok(Value::Int(quotient.to_int()? - 1))
+                } else {
+                    Ok(quotient)
+                }
+            }
+            (Value::Float(a), Value::Float(b)) => Self::floor_float_quotient(a, b),
+            (Value::Int(a), Value::Float(b)) => Self::floor_float_quotient(a as f64, b),
+            (Value::Float(a), Value::Int(b)) => Self::floor_float_quotient(a, b as f64),
+            (left, right) => Err(RuntimeError::TypeMismatch {
+                expected: "numeric".to_string(),
+                actual: format!("{} and {}", left.type_name(), right.type_name()),
+            }),
+        }
+    }
+
+    fn floor_float_quotient(a: f64, b: f64) -> Result<Value, RuntimeError> {
+        if b == 0.0 {
+            return Err(RuntimeError::DivisionByZero);
+        }
+        let quotient = (a / b).floor();
+        // `as` would saturate out-of-range values and turn NaN into 0; -2^63 is exact, 2^63 is not an i64
+        let in_range = quotient >= i64::MIN as f64 && quotient < -(i64::MIN as f64);
+        Self::checked_int(in_range.then_some(quotient as i64))
+    }
+
     fn modulo_values(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
             (Value::Int(a), Value::Int(b)) => {
                 if b == 0 {
                     Err(RuntimeError::DivisionByZero)
                 } else {
-                    Ok(Value::Int(a % b))
+                    Self::checked_int(a.checked_rem(b))
                 }
             }
             _ => Err(RuntimeError::TypeMismatch {
@@ -745,7 +2484,9 @@ impl Interpreter {
 
     fn shift_left(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a << b)),
+            (Value::Int(a), Value::Int(b)) => {
+                Self::checked_int(u32::try_from(b).ok().and_then(|shift| a.checked_shl(shift)))
+            }
             _ => Err(RuntimeError::TypeMismatch {
                 expected: "Int".to_string(),
                 actual: "something else you stupidly entered".to_string(),
@@ -755,7 +2496,9 @@ impl Interpreter {
 
     fn shift_right(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a >> b)),
+            (Value::Int(a), Value::Int(b)) => {
+                Self::checked_int(u32::try_from(b).ok().and_then(|shift| a.checked_shr(shift)))
+            }
             _ => Err(RuntimeError::TypeMismatch {
                 expected: "Int".to_string(),
                 actual: "something else you stupidly entered".to_string(),
@@ -763,6 +2506,24 @@ impl Interpreter {
         }
     }
 
+    fn compare_with_op(
+        &self,
+        op: &TokenKind,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, RuntimeError> {
+        match op {
+            TokenKind::Less => self.compare_values(left, right, |a, b| a < b),
+            TokenKind::Greater => self.compare_values(left, right, |a, b| a > b),
+            TokenKind::LessEqual => self.compare_values(left, right, |a, b| a <= b),
+            TokenKind::GreaterEqual => self.compare_values(left, right, |a, b| a >= b),
+            _ => Err(RuntimeError::Custom(format!(
+                "Unsupported comparison operator: {:?}",
+                op
+            ))),
+        }
+    }
+
     fn compare_values<F>(&self, left: Value, right: Value, op: F) -> Result<Value, RuntimeError>
     where
         F: Fn(f64, f64) -> bool,
@@ -772,15 +2533,103 @@ impl Interpreter {
             (Value::Float(a), Value::Float(b)) => Ok(Value::Bool(op(a, b))),
             (Value::Int(a), Value::Float(b)) => Ok(Value::Bool(op(a as f64, b))),
             (Value::Float(a), Value::Int(b)) => Ok(Value::Bool(op(a, b as f64))),
-            _ => Err(RuntimeError::TypeMismatch {
-                expected: "numeric".to_string(),
-                actual: "something else you stupidly entered".to_string(),
+            // strings and chars compare lexicographically; the ordering is fed to `op` as -1/0/1
+            (Value::String(a), Value::String(b)) => {
+                Ok(Value::Bool(op(a.cmp(&b) as i8 as f64, 0.0)))
+            }
+            (Value::Char(a), Value::Char(b)) => Ok(Value::Bool(op(a.cmp(&b) as i8 as f64, 0.0))),
+            (left, right) => Err(RuntimeError::TypeMismatch {
+                expected: "two numbers, strings or chars".to_string(),
+                actual: format!("{} and {}", left.type_name(), right.type_name()),
             }),
         }
     }
 
-    fn values_equal(&self, left: &Value, right: &Value) -> bool {
+    // bottom-up merge sort behind `sort`: stable, stops at the comparator's first error, and
+    // still terminates with some permutation when the comparator is not a consistent order
+    fn merge_sort(
+        items: Vec<Value>,
+        compare: &mut dyn FnMut(&Value, &Value) -> Result<std::cmp::Ordering, RuntimeError>,
+    ) -> Result<Vec<Value>, RuntimeError> {
+        let len = items.len();
+        let mut items = items;
+        let mut width = 1;
+        while width < len {
+            let mut merged = Vec::with_capacity(len);
+            let mut source = items.into_iter().peekable();
+            while source.peek().is_some() {
+                let mut left = source
+                    .by_ref()
+                    .take(width)
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .peekable();
+                let mut right = source
+                    .by_ref()
+                    .take(width)
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .peekable();
+                while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+                    // only a strictly greater left side moves the right element first
+                    if compare(a, b)? == std::cmp::Ordering::Greater {
+                        merged.extend(right.next());
+                    } else {
+                        merged.extend(left.next());
+                    }
+                }
+                merged.extend(left);
+                merged.extend(right);
+            }
+            items = merged;
+            width *= 2;
+        }
+        Ok(items)
+    }
+
+    // ordering shared by `sort`; None when the two values are not comparable
+    fn value_ordering(left: &Value, right: &Value) -> Option<std::cmp::Ordering> {
         match (left, right) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Char(a), Value::Char(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    // a type name or struct name tests the subject's type; anything else compares by value
+    fn pattern_matches(&mut self, subject: &Value, pattern: &Expr) -> Result<bool, RuntimeError> {
+        if let ExprKind::Identifier(name) = &pattern.inner
+            && TYPE_PATTERNS.contains(&name.as_str())
+            && !self.env.has_variable(name)
+        {
+            return Ok(subject.type_name() == name);
+        }
+        let pattern_value = self.interpret_expression(pattern)?;
+        if let (Value::Object { type_name, .. }, Value::TypeRef(TypeDef::Struct { name, .. })) =
+            (subject, &pattern_value)
+        {
+            return Ok(type_name == name);
+        }
+        self.values_equal(subject, &pattern_value)
+    }
+
+    fn values_equal(&self, left: &Value, right: &Value) -> Result<bool, RuntimeError> {
+        Self::values_equal_at(left, right, 0)
+    }
+
+    // structural equality; lists compare in order, objects and maps by their entries
+    fn values_equal_at(left: &Value, right: &Value, depth: usize) -> Result<bool, RuntimeError> {
+        if depth > MAX_EQUALITY_DEPTH {
+            return Err(RuntimeError::Custom(format!(
+                "values nested more than {} levels deep cannot be compared",
+                MAX_EQUALITY_DEPTH
+            )));
+        }
+        Ok(match (left, right) {
             (Value::Int(a), Value::Int(b)) => a == b,
             (Value::Float(a), Value::Float(b)) => a == b,
             (Value::Int(a), Value::Float(b)) => *a as f64 == *b,
@@ -789,8 +2638,61 @@ impl Interpreter {
             (Value::Char(a), Value::Char(b)) => a == b,
             (Value::Bool(a), Value::Bool(b)) => a == b,
             (Value::Null, Value::Null) => true,
+            (Value::List(a), Value::List(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (x, y) in a.iter().zip(b) {
+                    if !Self::values_equal_at(x, y, depth + 1)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            (
+                Value::Object {
+                    type_name: a_type,
+                    fields: a,
+                    ..
+                },
+                Value::Object {
+                    type_name: b_type,
+                    fields: b,
+                    ..
+                },
+            ) => {
+                if a_type != b_type || a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (name, x) in a {
+                    match b.get(name) {
+                        Some(y) if Self::values_equal_at(x, y, depth + 1)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                true
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (key, x) in a {
+                    match b.get(key) {
+                        Some(y) if Self::values_equal_at(x, y, depth + 1)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                true
+            }
+            // tools and types are equal when they name the same definition
+            (Value::ToolRef { name: a, .. }, Value::ToolRef { name: b, .. }) => a == b,
+            (Value::TypeRef(a), Value::TypeRef(b)) => match (a, b) {
+                (TypeDef::Struct { name: a, .. }, TypeDef::Struct { name: b, .. })
+                | (TypeDef::Template { name: a, .. }, TypeDef::Template { name: b, .. }) => a == b,
+                _ => false,
+            },
             _ => false,
-        }
+        })
     }
 
     fn create_object_from_typedef(
@@ -798,7 +2700,7 @@ impl Interpreter {
         type_def: TypeDef,
         field_inits: &[FieldInit],
     ) -> Result<Value, RuntimeError> {
-        let mut fields = std::collections::HashMap::new();
+        let mut fields = IndexMap::new();
         for field_init in field_inits {
             let value = self.interpret_expression(&field_init.value)?;
             fields.insert(field_init.name.clone(), value);
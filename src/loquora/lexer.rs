@@ -1,19 +1,79 @@
 use crate::loquora::token::{Token, TokenKind};
+use std::rc::Rc;
 
+// decodes the escape sequences shared by string and char literals
+pub fn unescape(raw: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        let decoded = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('$') => '$',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .map(char::from)
+                    .ok_or_else(|| format!("invalid hex escape \\x{}", hex))?
+            }
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err("expected { after \\u".to_string());
+                }
+                let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid unicode escape \\u{{{}}}", hex))?
+            }
+            Some(other) => return Err(format!("unknown escape \\{}", other)),
+            None => return Err("unterminated escape".to_string()),
+        };
+        out.push(decoded);
+    }
+    Ok(out)
+}
+
+// shared, so the parser's lookahead copies of a lexer don't copy the source
 #[derive(Clone)]
 pub struct Lexer {
-    input: String,
-    chars: Vec<char>,
+    input: Rc<str>,
+    chars: Rc<[char]>,
+    // byte offset of each char plus one past the end, so a char span slices in O(1)
+    offsets: Rc<[usize]>,
+    // where `input` starts in the source it was cut from; spans count from there
+    base: usize,
     index: usize,
+    // set once the iterator has handed out EOF
+    finished: bool,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Self {
-        let chars: Vec<char> = input.chars().collect();
+        let chars: Rc<[char]> = input.chars().collect();
+        let offsets: Rc<[usize]> = input
+            .char_indices()
+            .map(|(byte, _)| byte)
+            .chain(std::iter::once(input.len()))
+            .collect();
         Lexer {
-            input,
+            input: input.into(),
             chars,
+            offsets,
+            base: 0,
             index: 0,
+            finished: false,
         }
     }
 
@@ -21,6 +81,38 @@ impl Lexer {
         &self.input
     }
 
+    /// The part of the source `token` was lexed from. Spans count chars, so this
+    /// also holds up when the source has non-ASCII text before the token.
+    ///
+    /// ```
+    /// use loquora::loquora::lexer::Lexer;
+    ///
+    /// let mut lexer = Lexer::new("print(\"héllo\", name);".to_string());
+    /// let mut texts = Vec::new();
+    /// while let Some(tok) = lexer.next() {
+    ///     texts.push(lexer.text(&tok).to_string());
+    /// }
+    /// assert_eq!(texts[0], "print");
+    /// assert_eq!(texts[2], "\"héllo\"");
+    /// assert_eq!(texts[4], "name");
+    /// ```
+    pub fn text(&self, token: &Token) -> &str {
+        self.slice(token.span.start - self.base, token.span.end - self.base)
+    }
+
+    /// Lexes `input` as the part of a larger source that starts `base` chars in, so
+    /// its spans point into that source. Used for the code inside `${...}`.
+    pub fn with_base(input: String, base: usize) -> Self {
+        Lexer {
+            base,
+            ..Self::new(input)
+        }
+    }
+
+    fn slice(&self, start: usize, end: usize) -> &str {
+        &self.input[self.offsets[start]..self.offsets[end]]
+    }
+
     fn peek(&self) -> Option<char> {
         self.chars.get(self.index).copied()
     }
@@ -38,7 +130,7 @@ impl Lexer {
     }
 
     fn make_token(&self, kind: TokenKind, start: usize, end: usize) -> Token {
-        Token::new(kind, start..end)
+        Token::new(kind, self.base + start..self.base + end)
     }
 
     fn is_ident_start(ch: char) -> bool {
@@ -80,6 +172,17 @@ impl Lexer {
     }
 
     fn lex_number(&mut self, start: usize) -> Token {
+        // 0x / 0b / 0o literals take every letter and digit so the parser can point at bad ones
+        if self.peek() == Some('0')
+            && matches!(self.peek_n(1), Some('x' | 'X' | 'b' | 'B' | 'o' | 'O'))
+        {
+            self.advance();
+            self.advance();
+            while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+                self.advance();
+            }
+            return self.make_token(TokenKind::Int, start, self.index);
+        }
         let mut saw_dot = false;
         while let Some(ch) = self.peek() {
             if ch.is_ascii_digit() {
@@ -112,7 +215,7 @@ impl Lexer {
             self.advance();
         }
         let end = self.index;
-        let slice = &self.input[start..end];
+        let slice = self.slice(start, end);
         let kind = match slice {
             "load" => TokenKind::Load,
             "load_and_run" => TokenKind::LoadAndRun,
@@ -123,6 +226,8 @@ impl Lexer {
             "if" => TokenKind::If,
             "else" => TokenKind::Else,
             "elif" => TokenKind::Elif,
+            "match" => TokenKind::Match,
+            "do" => TokenKind::Do,
             "while" => TokenKind::While,
             "for" => TokenKind::For,
             "in" => TokenKind::In,
@@ -131,10 +236,15 @@ impl Lexer {
             "as" => TokenKind::As,
             "return" => TokenKind::Return,
             "break" => TokenKind::Break,
+            "let" => TokenKind::Let,
             "continue" => TokenKind::Continue,
             "true" => TokenKind::True,
             "false" => TokenKind::False,
             "null" => TokenKind::Null,
+            // word spellings of the logical operators share their symbolic tokens
+            "and" => TokenKind::LogicalAnd,
+            "or" => TokenKind::LogicalOr,
+            "not" => TokenKind::LogicalNot,
             _ => TokenKind::Identifier,
         };
         self.make_token(kind, start, end)
@@ -154,6 +264,11 @@ impl Lexer {
                         self.advance();
                     }
                 }
+                '$' if self.peek_n(1) == Some('{') => {
+                    self.advance();
+                    self.advance();
+                    self.skip_interpolation();
+                }
                 _ => {
                     self.advance();
                 }
@@ -162,25 +277,73 @@ impl Lexer {
         self.make_token(TokenKind::String, start, self.index)
     }
 
-    fn lex_char(&mut self, start: usize) -> Token {
-        if self.peek() == Some('\\') {
-            self.advance();
-            if self.peek().is_some() {
-                self.advance();
-            }
-        } else {
-            if self.peek().is_some() {
-                self.advance();
+    // skips the expression of a `${...}` up to its closing brace, so quotes inside it
+    // don't end the surrounding string; the parser splits the literal later
+    fn skip_interpolation(&mut self) {
+        let mut depth = 1;
+        while let Some(ch) = self.advance() {
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                '"' => {
+                    self.lex_string(self.index - 1);
+                }
+                _ => {}
             }
         }
-        if self.peek() == Some('\'') {
+    }
+
+    // r"..." or r#"..."#: no escapes, ends at a quote followed by the same number of #s
+    fn lex_raw_string(&mut self, start: usize, hashes: usize) -> Token {
+        for _ in 0..hashes + 2 {
             self.advance();
         }
+        while let Some(ch) = self.advance() {
+            if ch == '"' && (0..hashes).all(|n| self.peek_n(n) == Some('#')) {
+                for _ in 0..hashes {
+                    self.advance();
+                }
+                break;
+            }
+        }
+        self.make_token(TokenKind::RawString, start, self.index)
+    }
+
+    fn lex_char(&mut self, start: usize) -> Token {
+        // scan to the closing quote so multi-character escapes like '\u{41}' stay in one token
+        while let Some(ch) = self.peek() {
+            match ch {
+                '\'' => {
+                    self.advance();
+                    break;
+                }
+                '\n' => break,
+                '\\' => {
+                    self.advance();
+                    if self.peek().is_some() {
+                        self.advance();
+                    }
+                }
+                _ => {
+                    self.advance();
+                }
+            }
+        }
         self.make_token(TokenKind::Char, start, self.index)
     }
 
     fn lex_heredoc(&mut self, _start: usize) -> Token {
         // After <<~, read delimiter (identifier), then read until a line that exactly matches it
+        // a quoted delimiter (<<~'RAW') turns off ${...} interpolation for the body
+        let raw = self.peek() == Some('\'');
+        if raw {
+            self.advance();
+        }
         let delim_start = self.index;
         while let Some(c) = self.peek() {
             if Self::is_ident_continue(c) {
@@ -190,7 +353,10 @@ impl Lexer {
             }
         }
         let delim_end = self.index;
-        let delimiter = self.input[delim_start..delim_end].to_string();
+        let delimiter: String = self.chars[delim_start..delim_end].iter().collect();
+        if raw && self.peek() == Some('\'') {
+            self.advance();
+        }
         let delim_len = delimiter.len();
         if self.peek() == Some('\n') {
             self.advance();
@@ -207,15 +373,16 @@ impl Lexer {
                 self.index += 1;
             }
             let line_end = self.index;
-            let slice = &self.input[line_start..line_end];
-            let is_delim_exact = (line_end - line_start) == delim_len && slice == delimiter;
-            let is_delim_with_semicolon = (line_end - line_start) == delim_len + 1
-                && &self.input[line_start..line_start + delim_len] == delimiter
-                && &self.input[line_start + delim_len..line_end] == ";";
+            // the closing delimiter may be indented like the code around the heredoc
+            let line: String = self.chars[line_start..line_end].iter().collect();
+            let indent = line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+            let rest = &line[indent..];
+            let is_delim_exact = rest == delimiter;
+            let is_delim_with_semicolon = rest.strip_suffix(';') == Some(delimiter.as_str());
             let is_delim = is_delim_exact || is_delim_with_semicolon;
             if is_delim {
                 if is_delim_with_semicolon {
-                    let semicolon_pos = line_start + delim_len;
+                    let semicolon_pos = line_start + indent + delim_len;
                     self.index = semicolon_pos;
                 } else {
                     if self.index < total_len && self.chars[self.index] == '\n' {
@@ -230,7 +397,12 @@ impl Lexer {
                 end_of_token = self.index;
             }
         }
-        self.make_token(TokenKind::MultilineString, body_start, end_of_token)
+        let kind = if raw {
+            TokenKind::RawMultilineString
+        } else {
+            TokenKind::MultilineString
+        };
+        self.make_token(kind, body_start, end_of_token)
     }
 
     pub fn next_token(&mut self) -> Token {
@@ -272,6 +444,13 @@ impl Lexer {
                 return self.lex_number(start);
             }
 
+            if ch == 'r' {
+                let hashes = (1..).take_while(|&n| self.peek_n(n) == Some('#')).count();
+                if self.peek_n(hashes + 1) == Some('"') {
+                    return self.lex_raw_string(start, hashes);
+                }
+            }
+
             if Self::is_ident_start(ch) {
                 self.advance();
                 return self.lex_identifier_or_keyword(start);
@@ -287,6 +466,13 @@ impl Lexer {
                 return self.lex_char(start);
             }
 
+            if ch == '.' && self.peek_n(1) == Some('.') && self.peek_n(2) == Some('.') {
+                self.advance();
+                self.advance();
+                self.advance();
+                return self.make_token(TokenKind::Ellipsis, start, self.index);
+            }
+
             match (ch, self.peek_n(1)) {
                 ('&', Some('&')) => {
                     self.advance();
@@ -303,6 +489,11 @@ impl Lexer {
                     self.advance();
                     return self.make_token(TokenKind::EqualEqual, start, self.index);
                 }
+                ('=', Some('>')) => {
+                    self.advance();
+                    self.advance();
+                    return self.make_token(TokenKind::FatArrow, start, self.index);
+                }
                 ('!', Some('=')) => {
                     self.advance();
                     self.advance();
@@ -328,6 +519,11 @@ impl Lexer {
                     self.advance();
                     return self.make_token(TokenKind::ShiftRight, start, self.index);
                 }
+                ('?', Some(':')) => {
+                    self.advance();
+                    self.advance();
+                    return self.make_token(TokenKind::Elvis, start, self.index);
+                }
                 ('?', Some('?')) => {
                     self.advance();
                     self.advance();
@@ -343,6 +539,17 @@ impl Lexer {
                     self.advance();
                     return self.make_token(TokenKind::BangBang, start, self.index);
                 }
+                // `//` already starts a comment, so floor division is spelled `~/`
+                ('~', Some('/')) => {
+                    self.advance();
+                    self.advance();
+                    return self.make_token(TokenKind::FloorDiv, start, self.index);
+                }
+                ('#', Some('{')) => {
+                    self.advance();
+                    self.advance();
+                    return self.make_token(TokenKind::HashBrace, start, self.index);
+                }
                 ('-', Some('>')) => {
                     self.advance();
                     self.advance();
@@ -444,6 +651,14 @@ impl Lexer {
                     self.advance();
                     return self.make_token(TokenKind::RightBrace, start, self.index);
                 }
+                '[' => {
+                    self.advance();
+                    return self.make_token(TokenKind::LeftBracket, start, self.index);
+                }
+                ']' => {
+                    self.advance();
+                    return self.make_token(TokenKind::RightBracket, start, self.index);
+                }
                 _ => {
                     self.advance();
                     continue;
@@ -452,3 +667,27 @@ impl Lexer {
         }
     }
 }
+
+/// Yields every token up to and including `EOF`, then stops.
+///
+/// ```
+/// use loquora::loquora::lexer::Lexer;
+/// use loquora::loquora::token::TokenKind;
+///
+/// let tokens: Vec<_> = Lexer::new("let x = 1;".to_string()).collect();
+/// assert_eq!(tokens.len(), 6);
+/// assert_eq!(tokens[0].kind, TokenKind::Let);
+/// assert_eq!(tokens.last().unwrap().kind, TokenKind::EOF);
+/// ```
+impl Iterator for Lexer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
+        let tok = self.next_token();
+        self.finished = tok.kind == TokenKind::EOF;
+        Some(tok)
+    }
+}
@@ -0,0 +1,362 @@
+use crate::loquora::ast::*;
+use crate::loquora::token::Span;
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintWarning {
+    pub message: String,
+    pub span: Span,
+}
+
+// static pass over a parsed program; it only reports and never stops a run
+pub fn lint_program(program: &Program) -> Vec<LintWarning> {
+    let mut linter = Linter {
+        warnings: Vec::new(),
+        // the program's own top-level scope, so blocks can shadow its globals
+        scopes: vec![Vec::new()],
+    };
+    linter.stmts(&program.statements);
+    linter.warnings
+}
+
+struct Linter {
+    warnings: Vec<LintWarning>,
+    // names bound by `let`, tool parameters, loop variables and `with ... as`
+    scopes: Vec<Vec<String>>,
+}
+
+impl Linter {
+    fn warn(&mut self, message: String, span: &Span) {
+        self.warnings.push(LintWarning {
+            message,
+            span: span.clone(),
+        });
+    }
+
+    // binding a name again in the same scope only rebinds it, so that never warns
+    fn declare(&mut self, name: &str, span: &Span) {
+        let Some((scope, enclosing)) = self.scopes.split_last() else {
+            return;
+        };
+        if scope.iter().any(|n| n == name) {
+            return;
+        }
+        if enclosing
+            .iter()
+            .any(|outer| outer.iter().any(|n| n == name))
+        {
+            self.warn(format!("'{}' shadows an outer binding", name), span);
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(name.to_string());
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(Vec::new());
+        self.stmts(stmts);
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.inner {
+            StmtKind::ToolDecl {
+                name, params, body, ..
+            } => self.tool(name, params, body),
+            StmtKind::StructDecl { members, .. } => {
+                for member in members {
+                    if let StructMember::ToolDecl {
+                        name, params, body, ..
+                    } = member
+                    {
+                        self.tool(name, params, body);
+                    }
+                }
+            }
+            StmtKind::ExportDecl { decl } => self.stmt(decl),
+            StmtKind::ExportBlock { decls } => self.stmts(decls),
+            StmtKind::Let { name, .. } => self.declare(name, &stmt.span),
+            StmtKind::Destructure {
+                pattern,
+                declare: true,
+                ..
+            } => {
+                let names = match pattern {
+                    DestructurePattern::List { names, rest } => {
+                        names.iter().chain(rest).collect::<Vec<_>>()
+                    }
+                    DestructurePattern::Object { fields } => fields.iter().collect(),
+                };
+                for name in names {
+                    self.declare(name, &stmt.span);
+                }
+            }
+            StmtKind::With { binding, body, .. } => {
+                self.scopes.push(Vec::new());
+                if let Some(name) = binding {
+                    self.declare(name, &stmt.span);
+                }
+                self.stmts(body);
+                self.scopes.pop();
+            }
+            StmtKind::For {
+                index,
+                var,
+                body,
+                else_body,
+                ..
+            } => {
+                self.scopes.push(Vec::new());
+                if let Some(index) = index {
+                    self.declare(index, &stmt.span);
+                }
+                self.declare(var, &stmt.span);
+                self.stmts(body);
+                self.scopes.pop();
+                if let Some(else_body) = else_body {
+                    self.block(else_body);
+                }
+            }
+            StmtKind::Loop { body } | StmtKind::DoWhile { body, .. } => self.block(body),
+            StmtKind::While {
+                body, else_body, ..
+            } => {
+                self.block(body);
+                if let Some(else_body) = else_body {
+                    self.block(else_body);
+                }
+            }
+            StmtKind::If { arms, else_body } => {
+                for (_, body) in arms {
+                    self.block(body);
+                }
+                if let Some(else_body) = else_body {
+                    self.block(else_body);
+                }
+            }
+            StmtKind::Match { arms, default, .. } => {
+                for (_, body) in arms {
+                    self.block(body);
+                }
+                if let Some(default) = default {
+                    self.block(default);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn tool(&mut self, name: &str, params: &[ParamDecl], body: &[Stmt]) {
+        let mut used = HashSet::new();
+        stmt_uses(body, &mut used);
+        // a later parameter's default can be what reads an earlier one
+        for default in params.iter().filter_map(|param| param.default.as_ref()) {
+            expr_uses(default, &mut used);
+        }
+        self.scopes.push(Vec::new());
+        for param in params {
+            self.declare(&param.name, &param.ty.span);
+            // a leading underscore marks a parameter as deliberately unused
+            if !param.name.starts_with('_') && !used.contains(param.name.as_str()) {
+                self.warn(
+                    format!("parameter '{}' of '{}' is never used", param.name, name),
+                    &param.ty.span,
+                );
+            }
+        }
+        self.stmts(body);
+        self.scopes.pop();
+    }
+}
+
+// every identifier read anywhere in `stmts`, nested tools included
+fn stmt_uses<'a>(stmts: &'a [Stmt], used: &mut HashSet<&'a str>) {
+    for stmt in stmts {
+        match &stmt.inner {
+            StmtKind::ToolDecl { params, body, .. } => {
+                for param in params {
+                    if let Some(default) = &param.default {
+                        expr_uses(default, used);
+                    }
+                }
+                stmt_uses(body, used);
+            }
+            StmtKind::StructDecl { members, .. } => {
+                for member in members {
+                    if let StructMember::ToolDecl { body, .. } = member {
+                        stmt_uses(body, used);
+                    }
+                }
+            }
+            StmtKind::ExportDecl { decl } => stmt_uses(std::slice::from_ref(decl), used),
+            StmtKind::ExportBlock { decls } => stmt_uses(decls, used),
+            StmtKind::Assignment { target, value } => {
+                // `p.x = ...` reads p to update it
+                if target.len() > 1 {
+                    used.insert(target[0].as_str());
+                }
+                expr_uses(value, used);
+            }
+            StmtKind::Let { value, .. }
+            | StmtKind::Destructure { value, .. }
+            | StmtKind::ExprStmt { expr: value } => expr_uses(value, used),
+            StmtKind::With { expr, body, .. } => {
+                expr_uses(expr, used);
+                stmt_uses(body, used);
+            }
+            StmtKind::Loop { body } => stmt_uses(body, used),
+            StmtKind::If { arms, else_body } => {
+                for (cond, body) in arms {
+                    expr_uses(cond, used);
+                    stmt_uses(body, used);
+                }
+                if let Some(else_body) = else_body {
+                    stmt_uses(else_body, used);
+                }
+            }
+            StmtKind::Match {
+                subject,
+                arms,
+                default,
+            } => {
+                expr_uses(subject, used);
+                for (patterns, body) in arms {
+                    for pattern in patterns {
+                        expr_uses(pattern, used);
+                    }
+                    stmt_uses(body, used);
+                }
+                if let Some(default) = default {
+                    stmt_uses(default, used);
+                }
+            }
+            StmtKind::While {
+                cond,
+                body,
+                else_body,
+            } => {
+                expr_uses(cond, used);
+                stmt_uses(body, used);
+                if let Some(else_body) = else_body {
+                    stmt_uses(else_body, used);
+                }
+            }
+            StmtKind::DoWhile { body, cond } => {
+                stmt_uses(body, used);
+                expr_uses(cond, used);
+            }
+            StmtKind::For {
+                iter,
+                body,
+                else_body,
+                ..
+            } => {
+                expr_uses(iter, used);
+                stmt_uses(body, used);
+                if let Some(else_body) = else_body {
+                    stmt_uses(else_body, used);
+                }
+            }
+            StmtKind::Return { expr: Some(expr) } | StmtKind::Break { expr: Some(expr) } => {
+                expr_uses(expr, used)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn expr_uses<'a>(expr: &'a Expr, used: &mut HashSet<&'a str>) {
+    match &expr.inner {
+        ExprKind::Identifier(name) => {
+            used.insert(name.as_str());
+        }
+        ExprKind::Interpolation(parts) => {
+            for part in parts {
+                if let InterpolationPart::Expr(expr) = part {
+                    expr_uses(expr, used);
+                }
+            }
+        }
+        ExprKind::BinaryOp { left, right, .. } => {
+            expr_uses(left, used);
+            expr_uses(right, used);
+        }
+        ExprKind::UnaryOp { expr, .. } => expr_uses(expr, used),
+        ExprKind::ComparisonChain { operands, .. } => {
+            for operand in operands {
+                expr_uses(operand, used);
+            }
+        }
+        ExprKind::Ternary {
+            cond,
+            if_true,
+            if_false,
+        } => {
+            expr_uses(cond, used);
+            expr_uses(if_true, used);
+            expr_uses(if_false, used);
+        }
+        ExprKind::Coalesce { value, fallback } => {
+            expr_uses(value, used);
+            expr_uses(fallback, used);
+        }
+        ExprKind::Quaternary {
+            cond,
+            if_true,
+            if_false,
+            if_null,
+        } => {
+            expr_uses(cond, used);
+            expr_uses(if_true, used);
+            expr_uses(if_false, used);
+            expr_uses(if_null, used);
+        }
+        ExprKind::Call { callee, args } => {
+            expr_uses(callee, used);
+            for arg in args {
+                expr_uses(arg, used);
+            }
+        }
+        ExprKind::Property { object, .. } => expr_uses(object, used),
+        ExprKind::ObjectInit { type_expr, fields } => {
+            expr_uses(type_expr, used);
+            for field in fields {
+                expr_uses(&field.value, used);
+            }
+        }
+        ExprKind::MapLiteral { entries } => {
+            for (key, value) in entries {
+                expr_uses(key, used);
+                expr_uses(value, used);
+            }
+        }
+        ExprKind::ListLiteral { items } => {
+            for item in items {
+                expr_uses(item, used);
+            }
+        }
+        ExprKind::Spread(expr) => expr_uses(expr, used),
+        ExprKind::Loop { body } => stmt_uses(body, used),
+        ExprKind::IfExpr { arms, else_body } => {
+            for (cond, body) in arms {
+                expr_uses(cond, used);
+                stmt_uses(body, used);
+            }
+            if let Some(else_body) = else_body {
+                stmt_uses(else_body, used);
+            }
+        }
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Char(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Null => {}
+    }
+}
@@ -2,7 +2,199 @@ pub mod ast;
 pub mod environment;
 pub mod interpreter;
 pub mod lexer;
+pub mod lint;
 pub mod module;
 pub mod parser;
 pub mod token;
 pub mod value;
+pub mod vm;
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use ast::Program;
+use interpreter::Interpreter;
+use lexer::Lexer;
+use parser::Parser;
+use value::{RuntimeError, Value};
+
+/// Stack for the thread a program runs on. The default call depth limit relies on it: deep but
+/// legal recursion needs more room than a default 8MB (or 2MB spawned) thread stack.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum LoquoraError {
+    Parse(String),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LoquoraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoquoraError::Parse(msg) => write!(f, "Parse error: {}", msg),
+            LoquoraError::Runtime(error) => write!(f, "Runtime error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for LoquoraError {}
+
+impl From<RuntimeError> for LoquoraError {
+    fn from(error: RuntimeError) -> Self {
+        LoquoraError::Runtime(error)
+    }
+}
+
+/// Lexes, parses and runs `source`, returning the program's result.
+///
+/// The parser reports errors by panicking; those panics are caught here and
+/// come back as [`LoquoraError::Parse`].
+///
+/// The program runs on its own thread with a [`STACK_SIZE`] stack, so runaway
+/// recursion hits the call depth limit instead of overflowing the caller's stack.
+///
+/// ```
+/// use loquora::{eval, LoquoraError, Value};
+///
+/// let value = eval("let x = 2; x * 21;").unwrap();
+/// assert!(matches!(value, Value::Int(42)));
+/// assert!(eval("1 +;").is_err());
+///
+/// let runaway = eval("tool down(n: Int) -> Int { return down(n + 1); } down(0);");
+/// assert!(matches!(runaway, Err(LoquoraError::Runtime(_))));
+/// ```
+///
+/// Escapes in char and string literals are decoded, `\u{...}` and `\x..` included:
+///
+/// ```
+/// use loquora::{eval, Value};
+///
+/// assert_eq!(eval(r"'\n';").unwrap(), Value::Char('\n'));
+/// assert_eq!(eval(r"'\'';").unwrap(), Value::Char('\''));
+/// assert_eq!(eval(r"'\u{41}';").unwrap(), Value::Char('A'));
+/// assert_eq!(eval(r"'\x42';").unwrap(), Value::Char('B'));
+/// let literal = r#""tab:\there, \"quoted\" \\ \u{263A}";"#;
+/// let decoded = "tab:\there, \"quoted\" \\ \u{263A}".to_string();
+/// assert_eq!(eval(literal).unwrap(), Value::String(decoded));
+/// assert!(eval(r#""\q";"#).is_err());
+/// ```
+///
+/// A trailing parameter with a default can be left out; its default is evaluated at the
+/// call and can use the parameters before it:
+///
+/// ```
+/// use loquora::{eval, Value};
+///
+/// let scale = "tool scale(x: Int, factor: Int = 2, offset: Int = factor * 10) -> Int {
+///     return x * factor + offset;
+/// }";
+/// assert_eq!(eval(&format!("{scale} scale(1);")).unwrap(), Value::Int(22));
+/// assert_eq!(eval(&format!("{scale} scale(1, 3);")).unwrap(), Value::Int(33));
+/// assert_eq!(eval(&format!("{scale} scale(1, 3, 0);")).unwrap(), Value::Int(3));
+/// assert!(eval(&format!("{scale} scale();")).is_err());
+/// ```
+///
+/// A `<<~` heredoc drops the indentation its lines share; deeper lines keep the rest:
+///
+/// ```
+/// use loquora::{eval, Value};
+///
+/// let source = r#"
+///     let name = "Ada";
+///     <<~TEXT
+///         Dear ${name},
+///
+///           two spaces kept
+///         TEXT;
+/// "#;
+/// let letter = "Dear Ada,\n\n  two spaces kept".to_string();
+/// assert_eq!(eval(source).unwrap(), Value::String(letter));
+///
+/// let raw = "<<~'RAW'\n    ${kept}\n      deeper\n    RAW;";
+/// let kept = "${kept}\n  deeper".to_string();
+/// assert_eq!(eval(raw).unwrap(), Value::String(kept));
+/// ```
+///
+/// A struct method taking `self` is called on an object and gets a copy of it; one without
+/// is called on the type:
+///
+/// ```
+/// use loquora::{eval, LoquoraError, Value};
+///
+/// let counter = "struct Counter {
+///     n: Int,
+///     tool bumped(self) -> Int { self.n = self.n + 1; return self.n; }
+///     tool start() -> Counter { return Counter { n: 10 }; }
+/// }";
+/// let run = |code: &str| eval(&format!("{counter} {code}"));
+/// assert_eq!(run("Counter.start().bumped();").unwrap(), Value::Int(11));
+/// assert_eq!(run("let c = Counter { n: 1 }; c.bumped(); c.n;").unwrap(), Value::Int(1));
+/// assert_eq!(run("Counter.bumped(Counter { n: 5 });").unwrap(), Value::Int(6));
+/// let missing = run("let c = Counter { n: 1 }; c.missing();");
+/// assert!(matches!(missing, Err(LoquoraError::Runtime(_))));
+/// ```
+///
+/// A result outside Int's range is an error, never a wrapped or saturated number:
+///
+/// ```
+/// use loquora::eval;
+///
+/// let overflow = "Runtime error: integer overflow";
+/// assert_eq!(eval("9223372036854775807 + 1;").unwrap_err().to_string(), overflow);
+/// let min = "let min = -9223372036854775807 - 1;";
+/// for op in ["min - 1", "min * -1", "-min", "abs(min)", "min ~/ -1", "min % -1", "1 << 64"] {
+///     assert_eq!(eval(&format!("{min} {op};")).unwrap_err().to_string(), overflow);
+/// }
+/// assert_eq!(eval(&format!("{min} min + 1 - 1 == min;")).unwrap(), loquora::Value::Bool(true));
+/// assert_eq!(eval("1000000000000000000000.0 ~/ 1.0;").unwrap_err().to_string(), overflow);
+/// assert_eq!(eval("div(1000000000000000000000.0, 1.0);").unwrap_err().to_string(), overflow);
+/// assert_eq!(eval("float(\"NaN\") ~/ 1.0;").unwrap_err().to_string(), overflow);
+/// ```
+pub fn eval(source: &str) -> Result<Value, LoquoraError> {
+    std::thread::scope(|scope| {
+        let worker = std::thread::Builder::new()
+            .name("loquora".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || Interpreter::new().eval_source(source))
+            .expect("Failed to start interpreter thread");
+        worker
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+/// Parses without running anything, turning parser panics into [`LoquoraError::Parse`]
+/// the same way [`eval`] does.
+///
+/// The panic hook is swapped for a silent one while parsing, so a bad program doesn't
+/// print "thread panicked" to stderr. The hook is process-wide: a panic on another thread
+/// during the parse goes unreported too.
+///
+/// Nesting deeper than the parser's limit is one of those errors rather than a stack overflow:
+///
+/// ```
+/// use loquora::LoquoraError;
+/// use loquora::loquora::parse_source;
+///
+/// let deep = "(".repeat(10_000);
+/// assert!(matches!(parse_source(&deep), Err(LoquoraError::Parse(_))));
+/// assert!(matches!(loquora::eval(&deep), Err(LoquoraError::Parse(_))));
+/// ```
+pub fn parse_source(source: &str) -> Result<Program, LoquoraError> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+        Parser::new(Lexer::new(source.to_string())).parse_program()
+    }));
+    panic::set_hook(hook);
+    parsed.map_err(|payload| {
+        let msg = if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.clone()
+        } else if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg.to_string()
+        } else {
+            "invalid program".to_string()
+        };
+        LoquoraError::Parse(msg)
+    })
+}
@@ -3,33 +3,65 @@ use crate::loquora::environment::{ToolDef, TypeDef};
 use crate::loquora::interpreter::Interpreter;
 use crate::loquora::lexer::Lexer;
 use crate::loquora::parser::Parser;
+use crate::loquora::token::Span;
 use crate::loquora::value::RuntimeError;
+use indexmap::IndexMap;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// module files are lexed from here on, each past the end of the last, so their spans never
+// overlap the main source's or each other's and a span alone tells which file it is from
+const MODULE_SPAN_BASE: usize = usize::MAX / 2;
 
 #[derive(Clone, Debug)]
 pub struct Module {
     pub path: PathBuf,
     pub exports: ModuleExports,
     pub initialized: bool,
+    // whether its top-level code has executed; plain `load` only extracts exports
+    pub ran: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ModuleExports {
-    pub tools: HashMap<String, ToolDef>,
-    pub structs: HashMap<String, TypeDef>,
-    pub templates: HashMap<String, TypeDef>,
+    // in export order, so loading a module defines its names in a fixed order
+    pub tools: IndexMap<String, ToolDef>,
+    pub structs: IndexMap<String, TypeDef>,
+    pub templates: IndexMap<String, TypeDef>,
+}
+
+impl Default for ModuleExports {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ModuleExports {
     pub fn new() -> Self {
         ModuleExports {
-            tools: HashMap::new(),
-            structs: HashMap::new(),
-            templates: HashMap::new(),
+            tools: IndexMap::new(),
+            structs: IndexMap::new(),
+            templates: IndexMap::new(),
         }
     }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tools.contains_key(name)
+            || self.structs.contains_key(name)
+            || self.templates.contains_key(name)
+    }
+}
+
+// what a module run by `load_and_run` inherits from the interpreter that loaded it,
+// so a sandboxed or captured session stays that way inside its modules
+pub(crate) struct ModuleHost {
+    pub(crate) allow_io: bool,
+    pub(crate) max_call_depth: usize,
+    pub(crate) allow_shadowing: bool,
+    pub(crate) output: Box<dyn Write>,
 }
 
 pub struct ModuleCache {
@@ -37,6 +69,16 @@ pub struct ModuleCache {
     loading_stack: Vec<PathBuf>,
     stdlib: HashMap<String, Module>,
     search_paths: Vec<PathBuf>,
+    // the spans each parsed module file was given, see MODULE_SPAN_BASE
+    spans: Vec<(Range<usize>, PathBuf)>,
+    // lent by the loading interpreter for the duration of a load
+    pub(crate) host: Option<ModuleHost>,
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ModuleCache {
@@ -50,6 +92,8 @@ impl ModuleCache {
                 PathBuf::from("./src"),
                 PathBuf::from("./.loq/std"),
             ],
+            spans: Vec::new(),
+            host: None,
         };
 
         cache.init_stdlib();
@@ -83,9 +127,9 @@ impl ModuleCache {
         for search_path in &self.search_paths {
             let full_path = search_path.join(&file_path);
             if full_path.exists() {
-                return Ok(full_path.canonicalize().map_err(|e| {
+                return full_path.canonicalize().map_err(|e| {
                     RuntimeError::Custom(format!("Failed to canonicalize path: {}", e))
-                })?);
+                });
             }
         }
 
@@ -105,54 +149,130 @@ impl ModuleCache {
 
         if let Some(module) = self.modules.get(&file_path) {
             if !module.initialized {
-                return Err(RuntimeError::Custom(format!(
-                    "Circular import detected: {} is currently being loaded",
-                    file_path.display()
-                )));
+                return Err(self.circular_import_error(&file_path));
             }
-            return Ok(module.clone());
-        }
-
-        if self.loading_stack.contains(&file_path) {
-            return Err(RuntimeError::Custom(format!(
-                "Circular import detected: {}",
-                file_path.display()
-            )));
+            let module = module.clone();
+            if run && !module.ran {
+                // an earlier `load` skipped the top-level code, so run it the first time it is asked for
+                if let Some(cached) = self.modules.get_mut(&file_path) {
+                    cached.ran = true;
+                }
+                let program = self.parse_module(&file_path)?;
+                self.run_module(&file_path, &program)?;
+            }
+            return Ok(module);
         }
 
+        // registered before running so a load that cycles back finds it uninitialized
+        self.modules.insert(
+            file_path.clone(),
+            Module {
+                path: file_path.clone(),
+                exports: ModuleExports::new(),
+                initialized: false,
+                ran: run,
+            },
+        );
         self.loading_stack.push(file_path.clone());
+        let result = self.initialize_module(&file_path, run);
+        self.loading_stack.pop();
 
-        let source = fs::read_to_string(&file_path)
-            .map_err(|e| RuntimeError::Custom(format!("Failed to read module: {}", e)))?;
-
-        let lexer = Lexer::new(source);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program();
+        match result {
+            Ok(exports) => {
+                let module = Module {
+                    path: file_path.clone(),
+                    exports,
+                    initialized: true,
+                    ran: run,
+                };
+                self.modules.insert(file_path, module.clone());
+                Ok(module)
+            }
+            Err(error) => {
+                self.modules.remove(&file_path);
+                Err(error)
+            }
+        }
+    }
 
+    fn initialize_module(
+        &mut self,
+        file_path: &Path,
+        run: bool,
+    ) -> Result<ModuleExports, RuntimeError> {
+        let program = self.parse_module(file_path)?;
         if run {
-            let mut interpreter = Interpreter::new();
-            match interpreter.interpret_program(&program) {
-                Ok(result) => println!("Result for file path {}: {}", file_path.display(), result),
-                Err(error) => eprintln!(
-                    "Runtime Error for file path {}: {}",
-                    file_path.display(),
-                    error
-                ),
-            }
+            self.run_module(file_path, &program)?;
         }
+        self.extract_exports(&program)
+    }
 
-        let exports = self.extract_exports(&program)?;
+    fn parse_module(&mut self, file_path: &Path) -> Result<Program, RuntimeError> {
+        let source = fs::read_to_string(file_path)
+            .map_err(|e| RuntimeError::Custom(format!("Failed to read module: {}", e)))?;
 
-        let module = Module {
-            path: file_path.clone(),
-            exports,
-            initialized: true,
-        };
+        let base = self
+            .spans
+            .last()
+            .map_or(MODULE_SPAN_BASE, |(range, _)| range.end);
+        // one past the last char, where the EOF token sits
+        let end = base + source.chars().count() + 1;
+        self.spans.push((base..end, file_path.to_path_buf()));
+        let lexer = Lexer::with_base(source, base);
+        let mut parser = Parser::new(lexer);
+        Ok(parser.parse_program())
+    }
 
-        self.modules.insert(file_path.clone(), module.clone());
-        self.loading_stack.pop();
+    // the module file a span was parsed from, with the span made relative to that file;
+    // None for spans of the source the interpreter was given directly
+    pub(crate) fn locate(&self, span: &Span) -> Option<(PathBuf, Span)> {
+        let (range, path) = self
+            .spans
+            .iter()
+            .find(|(range, _)| range.contains(&span.start))?;
+        Some((
+            path.clone(),
+            span.start - range.start..span.end - range.start,
+        ))
+    }
+
+    // a runtime error in the module, a circular import included, fails the load itself
+    fn run_module(&mut self, file_path: &Path, program: &Program) -> Result<(), RuntimeError> {
+        let host = self
+            .host
+            .take()
+            .expect("modules are only run while the loading interpreter lends its host");
+        let mut interpreter = Interpreter::for_module(host);
+        // the module shares this cache so its own loads can see what is still loading
+        interpreter.swap_module_cache(self);
+        let result = interpreter.interpret_program(program);
+        interpreter.swap_module_cache(self);
+        let mut host = interpreter.module_host();
+        // through the importer's output, like everything the module printed
+        if let Ok(result) = &result {
+            let _ = writeln!(
+                host.output,
+                "Result for file path {}: {}",
+                file_path.display(),
+                result
+            );
+        }
+        self.host = Some(host);
+        result.map(|_| ())
+    }
 
-        Ok(module)
+    fn circular_import_error(&self, file_path: &Path) -> RuntimeError {
+        let start = self
+            .loading_stack
+            .iter()
+            .position(|path| path == file_path)
+            .unwrap_or(0);
+        let chain: Vec<String> = self.loading_stack[start..]
+            .iter()
+            .chain(std::iter::once(&file_path.to_path_buf()))
+            .map(|path| path.display().to_string())
+            .collect();
+        RuntimeError::Custom(format!("Circular import: {}", chain.join(" -> ")))
     }
 
     fn extract_exports(&mut self, program: &Program) -> Result<ModuleExports, RuntimeError> {
@@ -160,8 +280,11 @@ impl ModuleCache {
 
         for stmt in &program.statements {
             match &stmt.inner {
-                StmtKind::ExportDecl { decl } => {
-                    self.extract_export(&mut exports, decl)?;
+                StmtKind::ExportDecl { decl } => self.extract_export(&mut exports, decl)?,
+                StmtKind::ExportBlock { decls } => {
+                    for decl in decls {
+                        self.extract_export(&mut exports, decl)?;
+                    }
                 }
                 _ => {}
             }
@@ -175,11 +298,26 @@ impl ModuleCache {
         exports: &mut ModuleExports,
         decl: &Stmt,
     ) -> Result<(), RuntimeError> {
+        let declared = match &decl.inner {
+            StmtKind::ToolDecl { name, .. }
+            | StmtKind::StructDecl { name, .. }
+            | StmtKind::TemplateDecl { name, .. } => Some(name),
+            _ => None,
+        };
+        if let Some(name) = declared
+            && exports.contains(name)
+        {
+            return Err(RuntimeError::DuplicateDefinition(format!(
+                "export {}",
+                name
+            )));
+        }
+
         match &decl.inner {
             StmtKind::ToolDecl {
                 name,
                 params,
-                return_type: _,
+                return_type,
                 body,
             } => {
                 exports.tools.insert(
@@ -187,6 +325,7 @@ impl ModuleCache {
                     ToolDef {
                         name: name.clone(),
                         params: params.clone(),
+                        return_type: return_type.clone(),
                         body: body.clone(),
                     },
                 );
@@ -197,7 +336,7 @@ impl ModuleCache {
                     name.clone(),
                     TypeDef::Struct {
                         name: name.clone(),
-                        members: members.clone(),
+                        members: members.clone().into(),
                     },
                 );
             }
@@ -213,11 +352,41 @@ impl ModuleCache {
                 );
             }
 
-            _ => {
+            StmtKind::Load { path, alias: None } => {
+                let module = self.load_module(path, false)?;
+                let reexported = module.exports;
+                for name in reexported
+                    .tools
+                    .keys()
+                    .chain(reexported.structs.keys())
+                    .chain(reexported.templates.keys())
+                {
+                    if exports.contains(name) {
+                        return Err(RuntimeError::DuplicateDefinition(format!(
+                            "export {}",
+                            name
+                        )));
+                    }
+                }
+                exports.tools.extend(reexported.tools);
+                exports.structs.extend(reexported.structs);
+                exports.templates.extend(reexported.templates);
+            }
+
+            StmtKind::Load {
+                alias: Some(alias), ..
+            } => {
                 return Err(RuntimeError::Custom(format!(
-                    "Cannot export this declaration type"
+                    "Cannot re-export a module loaded as {}",
+                    alias
                 )));
             }
+
+            _ => {
+                return Err(RuntimeError::Custom(
+                    "Cannot export this declaration type".to_string(),
+                ));
+            }
         }
 
         Ok(())
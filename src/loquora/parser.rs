@@ -1,25 +1,57 @@
 use crate::loquora::ast::*;
-use crate::loquora::lexer::Lexer;
+use crate::loquora::lexer::{Lexer, unescape};
 use crate::loquora::token::{Span, Token, TokenKind};
 
+// deep enough for real programs while staying well inside the native stack
+const DEFAULT_MAX_DEPTH: usize = 256;
+
+// how the operators spelled across several tokens read in full, for when one is left unfinished
+const TERNARY_FORM: &str = "ternary `cond ? a : b`";
+const QUATERNARY_FORM: &str = "quaternary `cond ?? a :: b !! c`";
+
 pub struct Parser {
     lexer: Lexer,
     current: Token,
-    input: String,
     in_tool: bool,
     in_loop: usize,
+    // the innermost loop is a `loop`, the only kind whose `break` can carry a value
+    in_value_loop: bool,
+    // inside an `if` expression, where a branch's final expression may leave off its `;`
+    in_if_expr: usize,
+    // the struct whose method's parameter list comes next; a bare `self` there takes its type
+    method_of: Option<String>,
+    // nesting of expressions and statements currently being parsed
+    depth: usize,
+    max_depth: usize,
 }
 
 impl Parser {
     pub fn new(mut lexer: Lexer) -> Self {
-        let input = lexer.source().to_string();
         let current = lexer.next_token();
         Parser {
             lexer,
             current,
-            input,
             in_tool: false,
             in_loop: 0,
+            in_value_loop: false,
+            in_if_expr: 0,
+            method_of: None,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    fn enter_nesting(&mut self) {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            panic!(
+                "nesting exceeds the maximum depth of {} at span {:?}",
+                self.max_depth, self.current.span
+            );
         }
     }
 
@@ -70,8 +102,8 @@ impl Parser {
         self.parse_statement()
     }
 
-    fn slice_current<'a>(&'a self) -> &'a str {
-        &self.input[self.current.span.clone()]
+    fn slice_current(&self) -> &str {
+        self.lexer.text(&self.current)
     }
 
     fn parse_load_stmt_with_run(&mut self, run: bool) -> Stmt {
@@ -131,16 +163,20 @@ impl Parser {
         let start = self.current.span.start;
         self.eat(TokenKind::Export);
 
-        let decl = if self.at(TokenKind::Struct) {
-            self.parse_struct_decl()
-        } else if self.at(TokenKind::Tool) {
-            self.parse_tool_decl()
-        } else if self.at(TokenKind::Template) {
-            self.parse_template_decl()
-        } else {
-            panic!("Expected struct, tool, or template after export");
-        };
+        if self.at(TokenKind::LeftBrace) {
+            self.advance();
+            let mut decls = Vec::new();
+            while !self.at(TokenKind::RightBrace) && !self.at(TokenKind::EOF) {
+                decls.push(self.parse_exportable_decl());
+            }
+            self.eat(TokenKind::RightBrace);
+            return Spanned::new(
+                StmtKind::ExportBlock { decls },
+                start..self.current.span.start,
+            );
+        }
 
+        let decl = self.parse_exportable_decl();
         Spanned::new(
             StmtKind::ExportDecl {
                 decl: Box::new(decl),
@@ -149,6 +185,21 @@ impl Parser {
         )
     }
 
+    fn parse_exportable_decl(&mut self) -> Stmt {
+        if self.at(TokenKind::Struct) {
+            self.parse_struct_decl()
+        } else if self.at(TokenKind::Tool) {
+            self.parse_tool_decl()
+        } else if self.at(TokenKind::Template) {
+            self.parse_template_decl()
+        } else if self.at(TokenKind::Load) {
+            // `export load foo;` passes foo's exports on to our importers
+            self.parse_load_stmt_with_run(false)
+        } else {
+            panic!("Expected struct, tool, template, or load after export");
+        }
+    }
+
     fn is_assignment_start(&mut self) -> bool {
         if !self.at(TokenKind::Identifier) {
             return false;
@@ -167,18 +218,36 @@ impl Parser {
         }
     }
 
+    // `[a, b] = ...` rather than a list literal: the matching `]` is followed by `=`
+    fn is_list_pattern_start(&mut self) -> bool {
+        if !self.at(TokenKind::LeftBracket) {
+            return false;
+        }
+        let mut lx = self.lexer.clone();
+        let mut depth = 1;
+        loop {
+            match lx.next_token().kind {
+                TokenKind::LeftBracket => depth += 1,
+                TokenKind::RightBracket => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return matches!(lx.next_token().kind, TokenKind::Assign);
+                    }
+                }
+                TokenKind::EOF => return false,
+                _ => {}
+            }
+        }
+    }
+
     fn parse_assignable_path(&mut self) -> (Vec<String>, Span) {
         let mut parts = Vec::new();
         let start = self.current.span.start;
         let mut end = start;
-        loop {
-            if let TokenKind::Identifier = self.current.kind {
-                parts.push(self.slice_current().to_string());
-                end = self.current.span.end;
-                self.advance();
-            } else {
-                break;
-            }
+        while let TokenKind::Identifier = self.current.kind {
+            parts.push(self.slice_current().to_string());
+            end = self.current.span.end;
+            self.advance();
             if self.at(TokenKind::Dot) {
                 self.advance();
             } else {
@@ -189,17 +258,34 @@ impl Parser {
     }
 
     fn parse_expression(&mut self) -> Expr {
-        self.parse_quaternary()
+        self.enter_nesting();
+        let expr = self.parse_quaternary();
+        self.depth -= 1;
+        expr
     }
 
     fn parse_quaternary(&mut self) -> Expr {
         let left = self.parse_ternary();
         if self.at(TokenKind::QQuestion) {
+            let op_span = self.current.span.clone();
             self.advance();
             let if_true = self.parse_expression();
-            self.eat(TokenKind::DColon);
+            // without a `::` part this is plain null-coalescing, a ?? b
+            if !self.at(TokenKind::DColon) {
+                let start = left.span.start;
+                let end = if_true.span.end;
+                return Spanned::new(
+                    ExprKind::Coalesce {
+                        value: Box::new(left),
+                        fallback: Box::new(if_true),
+                    },
+                    start..end,
+                );
+            }
+            self.expect_operator_part(TokenKind::DColon, "`::`", QUATERNARY_FORM, &op_span);
             let if_false = self.parse_expression();
-            self.eat(TokenKind::BangBang);
+            // `a ?? b :: c` has no meaning of its own: the null case must be spelled out
+            self.expect_operator_part(TokenKind::BangBang, "`!!`", QUATERNARY_FORM, &op_span);
             let if_null = self.parse_quaternary();
             let start = left.span.start;
             let end = if_null.span.end;
@@ -219,9 +305,11 @@ impl Parser {
     fn parse_ternary(&mut self) -> Expr {
         let cond = self.parse_logical_or();
         if self.at(TokenKind::Question) {
+            let op_span = self.current.span.clone();
             self.advance();
             let if_true = self.parse_expression();
-            self.eat(TokenKind::Colon);
+            // `a ? b` has no else value; `a ?: b` is the null fallback
+            self.expect_operator_part(TokenKind::Colon, "`:`", TERNARY_FORM, &op_span);
             let if_false = self.parse_ternary();
             let start = cond.span.start;
             let end = if_false.span.end;
@@ -234,10 +322,47 @@ impl Parser {
                 start..end,
             );
         }
+        if self.at(TokenKind::Elvis) {
+            self.advance();
+            let fallback = self.parse_ternary();
+            let start = cond.span.start;
+            let end = fallback.span.end;
+            return Spanned::new(
+                ExprKind::Coalesce {
+                    value: Box::new(cond),
+                    fallback: Box::new(fallback),
+                },
+                start..end,
+            );
+        }
         cond
     }
 
+    // like `eat`, but points back at the operator that started the expression
+    fn expect_operator_part(&mut self, kind: TokenKind, part: &str, form: &str, op_span: &Span) {
+        if !self.at(kind) {
+            panic!(
+                "expected {} to complete {} started at span {:?}, found {:?} at span {:?}",
+                part, form, op_span, self.current.kind, self.current.span
+            );
+        }
+        self.advance();
+    }
+
     fn parse_statement(&mut self) -> Stmt {
+        self.enter_nesting();
+        let stmt = self.parse_statement_kind();
+        self.depth -= 1;
+        stmt
+    }
+
+    fn parse_statement_kind(&mut self) -> Stmt {
+        if self.at(TokenKind::Tool) {
+            return self.parse_tool_decl();
+        }
+        if self.at(TokenKind::Struct) {
+            return self.parse_struct_decl();
+        }
         if self.at(TokenKind::With) {
             return self.parse_with_stmt();
         }
@@ -247,9 +372,15 @@ impl Parser {
         if self.at(TokenKind::If) {
             return self.parse_if_stmt();
         }
+        if self.at(TokenKind::Match) {
+            return self.parse_match_stmt();
+        }
         if self.at(TokenKind::While) {
             return self.parse_while_stmt();
         }
+        if self.at(TokenKind::Do) {
+            return self.parse_do_while_stmt();
+        }
         if self.at(TokenKind::For) {
             return self.parse_for_stmt();
         }
@@ -262,9 +393,15 @@ impl Parser {
         if self.at(TokenKind::Continue) {
             return self.parse_continue_stmt();
         }
+        if self.at(TokenKind::Let) {
+            return self.parse_let_stmt();
+        }
         if self.is_assignment_start() {
             return self.parse_assignment_stmt();
         }
+        if self.at(TokenKind::LeftBrace) || self.is_list_pattern_start() {
+            return self.parse_destructure_stmt(self.current.span.start, false);
+        }
         self.parse_expr_stmt()
     }
 
@@ -272,29 +409,62 @@ impl Parser {
         let start = self.current.span.start;
         self.eat(TokenKind::With);
         let expr = self.parse_expression();
+        let binding = if self.at(TokenKind::As) {
+            self.advance();
+            if let TokenKind::Identifier = self.current.kind {
+                let name = self.slice_current().to_string();
+                self.advance();
+                Some(name)
+            } else {
+                panic!("Expected identifier after as in with");
+            }
+        } else {
+            None
+        };
         self.eat(TokenKind::LeftBrace);
         let body = self.parse_statements_until(TokenKind::RightBrace);
         self.eat(TokenKind::RightBrace);
         self.eat(TokenKind::Semicolon);
         Spanned::new(
-            StmtKind::With { expr, body },
+            StmtKind::With {
+                expr,
+                binding,
+                body,
+            },
             start..self.current.span.start,
         )
     }
 
     fn parse_loop_stmt(&mut self) -> Stmt {
         let start = self.current.span.start;
+        let body = self.parse_loop_block();
+        Spanned::new(StmtKind::Loop { body }, start..self.current.span.start)
+    }
+
+    fn parse_loop_block(&mut self) -> Vec<Stmt> {
         self.eat(TokenKind::Loop);
         self.eat(TokenKind::LeftBrace);
         self.in_loop += 1;
+        let was_value_loop = std::mem::replace(&mut self.in_value_loop, true);
         let body = self.parse_loop_body_until();
+        self.in_value_loop = was_value_loop;
         self.in_loop -= 1;
         self.eat(TokenKind::RightBrace);
-        Spanned::new(StmtKind::Loop { body }, start..self.current.span.start)
+        body
     }
 
     fn parse_if_stmt(&mut self) -> Stmt {
         let start = self.current.span.start;
+        let (arms, else_body) = self.parse_if_arms();
+        Spanned::new(
+            StmtKind::If { arms, else_body },
+            start..self.current.span.start,
+        )
+    }
+
+    // the conditions and bodies of an `if`/`elif` chain, then the `else` body if there is one
+    #[allow(clippy::type_complexity)]
+    fn parse_if_arms(&mut self) -> (Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>) {
         let mut arms: Vec<(Expr, Vec<Stmt>)> = Vec::new();
         self.eat(TokenKind::If);
         let cond = self.parse_expression();
@@ -319,8 +489,72 @@ impl Parser {
         } else {
             None
         };
+        (arms, else_body)
+    }
+
+    fn parse_match_stmt(&mut self) -> Stmt {
+        let start = self.current.span.start;
+        self.eat(TokenKind::Match);
+        let subject = self.parse_expression();
+        self.eat(TokenKind::LeftBrace);
+        let mut arms: Vec<(Vec<Expr>, Vec<Stmt>)> = Vec::new();
+        let mut default = None;
+        while !self.at(TokenKind::RightBrace) && !self.at(TokenKind::EOF) {
+            if default.is_some() {
+                panic!("match arms cannot follow the default arm");
+            }
+            let is_default = self.at(TokenKind::Else)
+                || (self.at(TokenKind::Identifier) && self.slice_current() == "_");
+            let mut patterns = Vec::new();
+            if is_default {
+                self.advance();
+            } else {
+                // patterns stop below `|` so it can separate alternatives
+                patterns.push(self.parse_unary());
+                while self.at(TokenKind::BitOr) {
+                    self.advance();
+                    patterns.push(self.parse_unary());
+                }
+            }
+            self.eat(TokenKind::FatArrow);
+            self.eat(TokenKind::LeftBrace);
+            let body = self.parse_statements_until(TokenKind::RightBrace);
+            self.eat(TokenKind::RightBrace);
+            if self.at(TokenKind::Comma) {
+                self.advance();
+            }
+            if is_default {
+                default = Some(body);
+            } else {
+                arms.push((patterns, body));
+            }
+        }
+        self.eat(TokenKind::RightBrace);
         Spanned::new(
-            StmtKind::If { arms, else_body },
+            StmtKind::Match {
+                subject,
+                arms,
+                default,
+            },
+            start..self.current.span.start,
+        )
+    }
+
+    fn parse_do_while_stmt(&mut self) -> Stmt {
+        let start = self.current.span.start;
+        self.eat(TokenKind::Do);
+        self.eat(TokenKind::LeftBrace);
+        self.in_loop += 1;
+        let was_value_loop = std::mem::replace(&mut self.in_value_loop, false);
+        let body = self.parse_loop_body_until();
+        self.in_value_loop = was_value_loop;
+        self.in_loop -= 1;
+        self.eat(TokenKind::RightBrace);
+        self.eat(TokenKind::While);
+        let cond = self.parse_expression();
+        self.eat(TokenKind::Semicolon);
+        Spanned::new(
+            StmtKind::DoWhile { body, cond },
             start..self.current.span.start,
         )
     }
@@ -331,34 +565,73 @@ impl Parser {
         let cond = self.parse_expression();
         self.eat(TokenKind::LeftBrace);
         self.in_loop += 1;
+        let was_value_loop = std::mem::replace(&mut self.in_value_loop, false);
         let body = self.parse_loop_body_until();
+        self.in_value_loop = was_value_loop;
         self.in_loop -= 1;
         self.eat(TokenKind::RightBrace);
+        let else_body = self.parse_loop_else();
         Spanned::new(
-            StmtKind::While { cond, body },
+            StmtKind::While {
+                cond,
+                body,
+                else_body,
+            },
             start..self.current.span.start,
         )
     }
 
+    fn parse_loop_else(&mut self) -> Option<Vec<Stmt>> {
+        if !self.at(TokenKind::Else) {
+            return None;
+        }
+        self.eat(TokenKind::Else);
+        self.eat(TokenKind::LeftBrace);
+        let body = self.parse_statements_until(TokenKind::RightBrace);
+        self.eat(TokenKind::RightBrace);
+        Some(body)
+    }
+
     fn parse_for_stmt(&mut self) -> Stmt {
         let start = self.current.span.start;
         self.eat(TokenKind::For);
-        let var = if let TokenKind::Identifier = self.current.kind {
+        let mut var = if let TokenKind::Identifier = self.current.kind {
             let v = self.slice_current().to_string();
             self.advance();
             v
         } else {
             panic!("Expected identifier after for");
         };
+        let mut index = None;
+        if self.at(TokenKind::Comma) {
+            self.advance();
+            let second = if let TokenKind::Identifier = self.current.kind {
+                let v = self.slice_current().to_string();
+                self.advance();
+                v
+            } else {
+                panic!("Expected identifier after , in for");
+            };
+            index = Some(std::mem::replace(&mut var, second));
+        }
         self.eat(TokenKind::In);
         let iter = self.parse_expression();
         self.eat(TokenKind::LeftBrace);
         self.in_loop += 1;
+        let was_value_loop = std::mem::replace(&mut self.in_value_loop, false);
         let body = self.parse_loop_body_until();
+        self.in_value_loop = was_value_loop;
         self.in_loop -= 1;
         self.eat(TokenKind::RightBrace);
+        let else_body = self.parse_loop_else();
         Spanned::new(
-            StmtKind::For { var, iter, body },
+            StmtKind::For {
+                index,
+                var,
+                iter,
+                body,
+                else_body,
+            },
             start..self.current.span.start,
         )
     }
@@ -366,20 +639,30 @@ impl Parser {
     fn parse_return_stmt(&mut self) -> Stmt {
         let start = self.current.span.start;
         self.eat(TokenKind::Return);
-        let expr = if self.at(TokenKind::Semicolon) {
+        let expr = if self.at(TokenKind::Semicolon) || self.at(TokenKind::EOF) {
             None
         } else {
             Some(self.parse_expression())
         };
-        self.eat(TokenKind::Semicolon);
+        self.eat_statement_end();
         Spanned::new(StmtKind::Return { expr }, start..self.current.span.start)
     }
 
     fn parse_break_stmt(&mut self) -> Stmt {
         let start = self.current.span.start;
         self.eat(TokenKind::Break);
+        let expr = if self.at(TokenKind::Semicolon) {
+            None
+        } else if !self.in_value_loop {
+            panic!(
+                "`break` with a value at span {:?} is only allowed in `loop`; while, do and for produce no value",
+                self.current.span
+            );
+        } else {
+            Some(self.parse_expression())
+        };
         self.eat(TokenKind::Semicolon);
-        Spanned::new(StmtKind::Break, start..self.current.span.start)
+        Spanned::new(StmtKind::Break { expr }, start..self.current.span.start)
     }
 
     fn parse_continue_stmt(&mut self) -> Stmt {
@@ -401,13 +684,93 @@ impl Parser {
         )
     }
 
+    fn parse_let_stmt(&mut self) -> Stmt {
+        let start = self.current.span.start;
+        self.eat(TokenKind::Let);
+        if self.at(TokenKind::LeftBracket) || self.at(TokenKind::LeftBrace) {
+            return self.parse_destructure_stmt(start, true);
+        }
+        if !self.at(TokenKind::Identifier) {
+            panic!("Expected identifier after let");
+        }
+        let name = self.slice_current().to_string();
+        self.advance();
+        self.eat(TokenKind::Assign);
+        let value = self.parse_expression();
+        self.eat(TokenKind::Semicolon);
+        Spanned::new(
+            StmtKind::Let { name, value },
+            start..self.current.span.start,
+        )
+    }
+
+    fn parse_destructure_stmt(&mut self, start: usize, declare: bool) -> Stmt {
+        let is_list = self.at(TokenKind::LeftBracket);
+        let close = if is_list {
+            TokenKind::RightBracket
+        } else {
+            TokenKind::RightBrace
+        };
+        self.advance();
+        let mut names = Vec::new();
+        let mut rest = None;
+        while !self.at(close.clone()) && !self.at(TokenKind::EOF) {
+            if rest.is_some() {
+                panic!("a rest element must come last in a destructuring pattern");
+            }
+            let spread = is_list && self.at(TokenKind::Ellipsis);
+            if spread {
+                self.advance();
+            }
+            if !self.at(TokenKind::Identifier) {
+                panic!("Expected identifier in destructuring pattern");
+            }
+            let name = self.slice_current().to_string();
+            self.advance();
+            if spread {
+                rest = Some(name);
+            } else {
+                names.push(name);
+            }
+            if !self.at(close.clone()) {
+                self.eat(TokenKind::Comma);
+            }
+        }
+        self.eat(close);
+        let pattern = if is_list {
+            DestructurePattern::List { names, rest }
+        } else {
+            DestructurePattern::Object { fields: names }
+        };
+        self.eat(TokenKind::Assign);
+        let value = self.parse_expression();
+        self.eat(TokenKind::Semicolon);
+        Spanned::new(
+            StmtKind::Destructure {
+                pattern,
+                value,
+                declare,
+            },
+            start..self.current.span.start,
+        )
+    }
+
     fn parse_expr_stmt(&mut self) -> Stmt {
         let start = self.current.span.start;
         let expr = self.parse_expression();
-        self.eat(TokenKind::Semicolon);
+        self.eat_statement_end();
         Spanned::new(StmtKind::ExprStmt { expr }, start..self.current.span.start)
     }
 
+    // the last statement of the input may leave off its semicolon, e.g. `1 + 2` in the REPL,
+    // and so may the last one of an `if` expression's branch, as in `if c { 1 } else { 2 }`
+    fn eat_statement_end(&mut self) {
+        let ends_branch = self.in_if_expr > 0 && self.at(TokenKind::RightBrace);
+        if !self.at(TokenKind::EOF) && !ends_branch {
+            self.eat(TokenKind::Semicolon);
+        }
+    }
+
     fn parse_statements_until(&mut self, end: TokenKind) -> Vec<Stmt> {
         let mut v = Vec::new();
         while !self.at(end.clone()) && !self.at(TokenKind::EOF) {
@@ -434,7 +797,7 @@ impl Parser {
 
     fn parse_type_expr(&mut self) -> TypeExpr {
         let start = self.current.span.start;
-        let name = match self.current.kind {
+        let mut name = match self.current.kind {
             TokenKind::Identifier => {
                 let s = self.slice_current().to_string();
                 self.advance();
@@ -442,6 +805,18 @@ impl Parser {
             }
             _ => panic!("type name expected"),
         };
+        // module-qualified names like `geo.Rectangle`
+        while self.at(TokenKind::Dot) {
+            self.advance();
+            match self.current.kind {
+                TokenKind::Identifier => {
+                    name.push('.');
+                    name.push_str(self.slice_current());
+                    self.advance();
+                }
+                _ => panic!("type name expected after ."),
+            }
+        }
         if self.at(TokenKind::Less) {
             self.eat(TokenKind::Less);
             let mut params: Vec<TypeExpr> = Vec::new();
@@ -464,12 +839,28 @@ impl Parser {
         Spanned::new(TypeExprKind::Name(name), start..self.current.span.start)
     }
 
+    // signature types may end in `?` to admit null; struct fields use their own suffixes instead
+    fn parse_signature_type(&mut self) -> TypeExpr {
+        let ty = self.parse_type_expr();
+        if self.at(TokenKind::Question) {
+            let start = ty.span.start;
+            self.advance();
+            return Spanned::new(
+                TypeExprKind::Optional(Box::new(ty)),
+                start..self.current.span.start,
+            );
+        }
+        ty
+    }
+
     fn parse_param_list(&mut self) -> Vec<ParamDecl> {
         let mut params = Vec::new();
+        let method_of = self.method_of.take();
         if self.at(TokenKind::RightParen) {
             return params;
         }
         loop {
+            let name_span = self.current.span.clone();
             let name = match self.current.kind {
                 TokenKind::Identifier => {
                     let s = self.slice_current().to_string();
@@ -478,9 +869,30 @@ impl Parser {
                 }
                 _ => panic!("param name expected"),
             };
-            self.eat(TokenKind::Colon);
-            let ty = self.parse_type_expr();
-            params.push(ParamDecl { name, ty });
+            let ty = match &method_of {
+                Some(owner)
+                    if params.is_empty() && name == "self" && !self.at(TokenKind::Colon) =>
+                {
+                    Spanned::new(TypeExprKind::Name(owner.clone()), name_span)
+                }
+                _ => {
+                    self.eat(TokenKind::Colon);
+                    self.parse_signature_type()
+                }
+            };
+            let default = if self.at(TokenKind::Assign) {
+                self.advance();
+                Some(self.parse_expression())
+            } else {
+                if params.iter().any(|p| p.default.is_some()) {
+                    panic!(
+                        "parameter {} without a default follows a defaulted parameter",
+                        name
+                    );
+                }
+                None
+            };
+            params.push(ParamDecl { name, ty, default });
             if self.at(TokenKind::Comma) {
                 self.advance();
             } else {
@@ -507,12 +919,13 @@ impl Parser {
         self.eat(TokenKind::LeftBrace);
         let body = match self.current.kind {
             TokenKind::String => {
-                let s = self.slice_current().trim_matches('"').to_string();
+                let s = self.string_literal_value();
                 self.advance();
                 s
             }
-            TokenKind::MultilineString => {
-                let s = self.slice_current().to_string();
+            TokenKind::MultilineString | TokenKind::RawMultilineString => {
+                let raw = self.slice_current();
+                let s = strip_indent(raw, heredoc_indent(raw), &mut true);
                 self.advance();
                 s
             }
@@ -541,6 +954,7 @@ impl Parser {
         let mut members: Vec<StructMember> = Vec::new();
         while !self.at(TokenKind::RightBrace) {
             if self.at(TokenKind::Tool) {
+                self.method_of = Some(name.clone());
                 let (n, p, r, b) = self.parse_tool_decl_inner();
                 members.push(StructMember::ToolDecl {
                     name: n,
@@ -548,7 +962,10 @@ impl Parser {
                     return_type: r,
                     body: b,
                 });
-                self.eat(TokenKind::Semicolon);
+                // methods end with `}` like any tool; a `;` after one is still accepted
+                if self.at(TokenKind::Semicolon) {
+                    self.advance();
+                }
             } else {
                 let fname = match self.current.kind {
                     TokenKind::Identifier => {
@@ -621,7 +1038,7 @@ impl Parser {
         self.eat(TokenKind::RightParen);
         let ret = if self.at(TokenKind::Arrow) {
             self.advance();
-            Some(self.parse_type_expr())
+            Some(self.parse_signature_type())
         } else {
             None
         };
@@ -651,20 +1068,45 @@ impl Parser {
     }
     fn parse_equality(&mut self) -> Expr {
         self.parse_left_assoc_bin(
-            |p| p.parse_relational(),
+            |p| p.parse_membership(),
             &[TokenKind::EqualEqual, TokenKind::NotEqual],
         )
     }
+    // `x in xs` sits just above the comparisons so it never joins a comparison chain
+    fn parse_membership(&mut self) -> Expr {
+        self.parse_left_assoc_bin(|p| p.parse_relational(), &[TokenKind::In])
+    }
     fn parse_relational(&mut self) -> Expr {
-        self.parse_left_assoc_bin(
-            |p| p.parse_shift(),
-            &[
-                TokenKind::Less,
-                TokenKind::Greater,
-                TokenKind::LessEqual,
-                TokenKind::GreaterEqual,
-            ],
-        )
+        let first = self.parse_shift();
+        let mut operands = vec![first];
+        let mut ops = Vec::new();
+        while self.at(TokenKind::Less)
+            || self.at(TokenKind::Greater)
+            || self.at(TokenKind::LessEqual)
+            || self.at(TokenKind::GreaterEqual)
+        {
+            ops.push(self.current.kind.clone());
+            self.advance();
+            operands.push(self.parse_shift());
+        }
+        let start = operands[0].span.start;
+        let end = operands[operands.len() - 1].span.end;
+        match ops.len() {
+            0 => operands.pop().unwrap(),
+            1 => {
+                let right = operands.pop().unwrap();
+                let left = operands.pop().unwrap();
+                Spanned::new(
+                    ExprKind::BinaryOp {
+                        op: ops.pop().unwrap(),
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    },
+                    start..end,
+                )
+            }
+            _ => Spanned::new(ExprKind::ComparisonChain { operands, ops }, start..end),
+        }
     }
     fn parse_shift(&mut self) -> Expr {
         self.parse_left_assoc_bin(
@@ -684,6 +1126,7 @@ impl Parser {
             &[
                 TokenKind::Multiply,
                 TokenKind::Divide,
+                TokenKind::FloorDiv,
                 TokenKind::Modulo,
                 TokenKind::At,
             ],
@@ -809,7 +1252,7 @@ impl Parser {
                 let mut args: Vec<Expr> = Vec::new();
                 if !self.at(TokenKind::RightParen) {
                     loop {
-                        let e = self.parse_expression();
+                        let e = self.parse_spreadable();
                         args.push(e);
                         if self.at(TokenKind::Comma) {
                             self.advance();
@@ -835,6 +1278,20 @@ impl Parser {
         node
     }
 
+    // a call argument or list item, which may be `...expr`
+    fn parse_spreadable(&mut self) -> Expr {
+        if !self.at(TokenKind::Ellipsis) {
+            return self.parse_expression();
+        }
+        let start = self.current.span.start;
+        self.advance();
+        let expr = self.parse_expression();
+        Spanned::new(
+            ExprKind::Spread(Box::new(expr)),
+            start..self.current.span.start,
+        )
+    }
+
     fn parse_primary(&mut self) -> Expr {
         match self.current.kind {
             TokenKind::Identifier => {
@@ -873,7 +1330,7 @@ impl Parser {
             }
             TokenKind::Int => {
                 let start = self.current.span.start;
-                let n = self.slice_current().parse::<i64>().unwrap();
+                let n = self.int_literal_value();
                 let end = self.current.span.end;
                 self.advance();
                 Spanned::new(ExprKind::Int(n), start..end)
@@ -887,26 +1344,87 @@ impl Parser {
             }
             TokenKind::String => {
                 let start = self.current.span.start;
-                let s = self.slice_current().trim_matches('"').to_string();
+                let kind = self.string_literal_expr();
+                let end = self.current.span.end;
+                self.advance();
+                Spanned::new(kind, start..end)
+            }
+            TokenKind::RawString => {
+                let start = self.current.span.start;
+                let raw = self.slice_current();
+                let hashes = raw[1..].chars().take_while(|c| *c == '#').count();
+                let closing = format!("\"{}", "#".repeat(hashes));
+                let s = raw[hashes + 2..]
+                    .strip_suffix(closing.as_str())
+                    .filter(|_| raw.len() >= 2 * hashes + 3)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "unterminated raw string literal at span {:?}",
+                            self.current.span
+                        )
+                    })
+                    .to_string();
                 let end = self.current.span.end;
                 self.advance();
                 Spanned::new(ExprKind::String(s), start..end)
             }
-            TokenKind::MultilineString => {
+            TokenKind::MultilineString | TokenKind::RawMultilineString => {
                 let start = self.current.span.start;
                 let mut s = self.slice_current().to_string();
                 // Remove trailing newline from heredoc strings
                 if s.ends_with('\n') {
                     s.pop();
                 }
+                let width = heredoc_indent(&s);
+                let kind = if self.at(TokenKind::MultilineString) && s.contains("${") {
+                    let chars: Vec<char> = s.chars().collect();
+                    // dedent after splitting so the embedded expressions keep their spans
+                    match self.interpolate(&chars, start, false) {
+                        ExprKind::Interpolation(mut parts) => {
+                            let mut line_start = true;
+                            for part in &mut parts {
+                                match part {
+                                    InterpolationPart::Literal(text) => {
+                                        *text = strip_indent(text, width, &mut line_start)
+                                    }
+                                    InterpolationPart::Expr(_) => line_start = false,
+                                }
+                            }
+                            ExprKind::Interpolation(parts)
+                        }
+                        other => other,
+                    }
+                } else {
+                    ExprKind::String(strip_indent(&s, width, &mut true))
+                };
                 let end = self.current.span.end;
                 self.advance();
-                Spanned::new(ExprKind::String(s), start..end)
+                Spanned::new(kind, start..end)
             }
             TokenKind::Char => {
                 let start = self.current.span.start;
                 let raw = self.slice_current();
-                let ch = raw.trim_matches('\'').chars().next().unwrap_or('\0');
+                let inner = raw
+                    .strip_prefix('\'')
+                    .and_then(|r| r.strip_suffix('\''))
+                    .filter(|r| !r.is_empty())
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "malformed char literal at span {:?}",
+                            start..self.current.span.end
+                        )
+                    });
+                let decoded = unescape(inner).unwrap_or_else(|e| {
+                    panic!("{} in char literal at span {:?}", e, self.current.span)
+                });
+                let mut chars = decoded.chars();
+                let ch = match (chars.next(), chars.next()) {
+                    (Some(ch), None) => ch,
+                    _ => panic!(
+                        "char literal must contain exactly one character, found {} at span {:?}",
+                        raw, self.current.span
+                    ),
+                };
                 let end = self.current.span.end;
                 self.advance();
                 Spanned::new(ExprKind::Char(ch), start..end)
@@ -916,6 +1434,60 @@ impl Parser {
                 self.advance();
                 Spanned::new(ExprKind::Bool(true), start..self.current.span.start)
             }
+            TokenKind::HashBrace => {
+                let start = self.current.span.start;
+                self.advance();
+                let mut entries = Vec::new();
+                while !self.at(TokenKind::RightBrace) {
+                    let key = self.parse_expression();
+                    self.eat(TokenKind::Colon);
+                    let value = self.parse_expression();
+                    entries.push((key, value));
+                    if self.at(TokenKind::Comma) {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.eat(TokenKind::RightBrace);
+                Spanned::new(
+                    ExprKind::MapLiteral { entries },
+                    start..self.current.span.start,
+                )
+            }
+            TokenKind::LeftBracket => {
+                let start = self.current.span.start;
+                self.advance();
+                let mut items = Vec::new();
+                while !self.at(TokenKind::RightBracket) && !self.at(TokenKind::EOF) {
+                    items.push(self.parse_spreadable());
+                    if self.at(TokenKind::Comma) {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.eat(TokenKind::RightBracket);
+                Spanned::new(
+                    ExprKind::ListLiteral { items },
+                    start..self.current.span.start,
+                )
+            }
+            TokenKind::Loop => {
+                let start = self.current.span.start;
+                let body = self.parse_loop_block();
+                Spanned::new(ExprKind::Loop { body }, start..self.current.span.start)
+            }
+            TokenKind::If => {
+                let start = self.current.span.start;
+                self.in_if_expr += 1;
+                let (arms, else_body) = self.parse_if_arms();
+                self.in_if_expr -= 1;
+                Spanned::new(
+                    ExprKind::IfExpr { arms, else_body },
+                    start..self.current.span.start,
+                )
+            }
             TokenKind::False => {
                 let start = self.current.span.start;
                 self.advance();
@@ -939,6 +1511,168 @@ impl Parser {
         }
     }
 
+    fn string_literal_value(&self) -> String {
+        let raw = self.slice_current();
+        let inner = raw
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .unwrap_or_else(|| {
+                panic!(
+                    "unterminated string literal at span {:?}",
+                    self.current.span
+                )
+            });
+        unescape(inner)
+            .unwrap_or_else(|e| panic!("{} in string literal at span {:?}", e, self.current.span))
+    }
+
+    fn int_literal_value(&self) -> i64 {
+        let text = self.slice_current();
+        let (digits, radix, kind) = match text.get(..2) {
+            Some("0x" | "0X") => (&text[2..], 16, "hexadecimal"),
+            Some("0b" | "0B") => (&text[2..], 2, "binary"),
+            Some("0o" | "0O") => (&text[2..], 8, "octal"),
+            _ => (text, 10, "integer"),
+        };
+        if digits.is_empty() {
+            panic!(
+                "{} literal {} has no digits at span {:?}",
+                kind, text, self.current.span
+            );
+        }
+        i64::from_str_radix(digits, radix).unwrap_or_else(|e| {
+            panic!(
+                "invalid {} literal {} ({}) at span {:?}",
+                kind, text, e, self.current.span
+            )
+        })
+    }
+
+    fn string_literal_expr(&self) -> ExprKind {
+        let raw = self.slice_current();
+        if !raw.contains("${") {
+            return ExprKind::String(self.string_literal_value());
+        }
+        if raw.len() < 2 || !raw.ends_with('"') {
+            panic!(
+                "unterminated string literal at span {:?}",
+                self.current.span
+            );
+        }
+        let chars: Vec<char> = raw.chars().collect();
+        self.interpolate(
+            &chars[1..chars.len() - 1],
+            self.current.span.start + 1,
+            true,
+        )
+    }
+
+    // splits `text` (starting at char `offset` in the source) into literal and `${...}`
+    // parts; heredocs pass `escapes: false` since their bodies are taken verbatim
+    fn interpolate(&self, chars: &[char], offset: usize, escapes: bool) -> ExprKind {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if escapes => {
+                    literal.push('\\');
+                    if let Some(&next) = chars.get(i + 1) {
+                        literal.push(next);
+                    }
+                    i += 2;
+                }
+                '$' if chars.get(i + 1) == Some(&'{') => {
+                    if !literal.is_empty() {
+                        parts.push(self.literal_part(&literal, escapes));
+                        literal.clear();
+                    }
+                    let expr_start = i + 2;
+                    let expr_end =
+                        Self::interpolation_end(chars, expr_start).unwrap_or_else(|| {
+                            panic!(
+                                "unterminated ${{ in string literal at span {:?}",
+                                self.current.span
+                            )
+                        });
+                    let text: String = chars[expr_start..expr_end].iter().collect();
+                    let expr = self.parse_interpolated_expr(&text, offset + expr_start);
+                    parts.push(InterpolationPart::Expr(expr));
+                    i = expr_end + 1;
+                }
+                ch => {
+                    literal.push(ch);
+                    i += 1;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(self.literal_part(&literal, escapes));
+        }
+        // only escaped `\${` sequences, nothing to interpolate
+        if let [InterpolationPart::Literal(text)] = parts.as_slice() {
+            return ExprKind::String(text.clone());
+        }
+        ExprKind::Interpolation(parts)
+    }
+
+    fn literal_part(&self, raw: &str, escapes: bool) -> InterpolationPart {
+        if escapes {
+            InterpolationPart::Literal(self.unescape_literal(raw))
+        } else {
+            InterpolationPart::Literal(raw.to_string())
+        }
+    }
+
+    fn unescape_literal(&self, raw: &str) -> String {
+        unescape(raw)
+            .unwrap_or_else(|e| panic!("{} in string literal at span {:?}", e, self.current.span))
+    }
+
+    // index of the `}` closing an interpolation, skipping braces inside nested strings
+    fn interpolation_end(chars: &[char], from: usize) -> Option<usize> {
+        let mut depth = 1;
+        let mut in_string = false;
+        let mut i = from;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if in_string => i += 1,
+                '"' => in_string = !in_string,
+                '{' if !in_string => depth += 1,
+                '}' if !in_string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        None
+    }
+
+    // parses the text of a `${...}` with its spans pointing into the original source
+    fn parse_interpolated_expr(&self, text: &str, offset: usize) -> Expr {
+        if text.trim().is_empty() {
+            panic!(
+                "empty ${{}} in string literal at span {:?}",
+                self.current.span
+            );
+        }
+        let mut parser = Parser::new(Lexer::with_base(text.to_string(), offset));
+        parser.depth = self.depth;
+        parser.max_depth = self.max_depth;
+        let expr = parser.parse_expression();
+        if !parser.at(TokenKind::EOF) {
+            panic!(
+                "unexpected {:?} at span {:?} in string interpolation",
+                parser.current.kind, parser.current.span
+            );
+        }
+        expr
+    }
+
     fn parse_field_init_list(&mut self) -> Vec<FieldInit> {
         self.eat(TokenKind::LeftBrace);
         let mut fields = Vec::new();
@@ -976,3 +1710,29 @@ impl Parser {
         fields
     }
 }
+
+// `<<~` bodies lose the indentation shared by their non-blank lines
+fn heredoc_indent(body: &str) -> usize {
+    body.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().take_while(|c| *c == ' ' || *c == '\t').count())
+        .min()
+        .unwrap_or(0)
+}
+
+// drops up to `width` leading spaces/tabs from every line; `line_start` carries over
+// between pieces of one body so text after an interpolation isn't touched
+fn strip_indent(text: &str, width: usize, line_start: &mut bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut skipped = 0;
+    for ch in text.chars() {
+        if *line_start && skipped < width && (ch == ' ' || ch == '\t') {
+            skipped += 1;
+            continue;
+        }
+        *line_start = ch == '\n';
+        skipped = 0;
+        out.push(ch);
+    }
+    out
+}
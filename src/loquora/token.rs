@@ -2,6 +2,7 @@ use std::ops::Range;
 
 pub type Span = Range<usize>;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TokenKind {
     // Literals
@@ -26,6 +27,8 @@ pub enum TokenKind {
     If,
     Else,
     Elif,
+    Match,
+    Do,
     While,
     For,
     In,
@@ -35,12 +38,14 @@ pub enum TokenKind {
     Return,
     Break,
     Continue,
+    Let,
 
     // Operators
     Plus,         // +
     Minus,        // -
     Multiply,     // *
     Divide,       // /
+    FloorDiv,     // ~/
     Modulo,       // %
     At,           // @
     BitAnd,       // &
@@ -60,24 +65,32 @@ pub enum TokenKind {
     ShiftRight,   // >>
     Assign,       // =
     Arrow,        // ->
+    FatArrow,     // =>
 
     // Quaternary and ternary parts
     Question,  // ?
     Colon,     // :
     QQuestion, // ??
+    Elvis,     // ?:
     DColon,    // ::
     BangBang,  // !!
 
     // Punctuation
-    Dot,        // .
-    Comma,      // ,
-    Semicolon,  // ;
-    LeftParen,  // (
-    RightParen, // )
-    LeftBrace,  // {
-    RightBrace, // }
+    Dot,          // .
+    Ellipsis,     // ...
+    Comma,        // ,
+    Semicolon,    // ;
+    LeftParen,    // (
+    RightParen,   // )
+    LeftBrace,    // {
+    HashBrace,    // #{
+    RightBrace,   // }
+    LeftBracket,  // [
+    RightBracket, // ]
 
-    MultilineString, // <<~...delimiter
+    MultilineString,    // <<~...delimiter
+    RawMultilineString, // <<~'...'delimiter, never interpolated
+    RawString,          // r"..." / r#"..."#, no escapes
 
     // End of input
     EOF,
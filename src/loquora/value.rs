@@ -1,8 +1,15 @@
-use crate::loquora::ast::{ParamDecl, Stmt};
-use crate::loquora::environment::{ToolDef, TypeDef};
-use std::collections::HashMap;
+use crate::loquora::ast::{ParamDecl, Stmt, TypeExpr, TypeExprKind};
+use crate::loquora::environment::{StructMembers, TypeDef};
+use crate::loquora::module::ModuleExports;
+use indexmap::IndexMap;
 use std::fmt;
 
+// type names `matches_type` always checks, whatever the program declares
+const BUILTIN_TYPES: [&str; 13] = [
+    "Any", "Int", "Float", "String", "Char", "Bool", "Null", "List", "Map", "Object", "Tool",
+    "Type", "Module",
+];
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Int(i64),
@@ -13,32 +20,169 @@ pub enum Value {
     Null,
     Object {
         type_name: String,
-        fields: HashMap<String, Value>,
+        // insertion-ordered so Display and iteration are deterministic
+        fields: IndexMap<String, Value>,
+        // those of the declaration it was built from, so its methods resolve wherever it ends up
+        members: StructMembers,
     },
     ToolRef {
         name: String,
         params: Vec<ParamDecl>,
+        return_type: Option<TypeExpr>,
         body: Vec<Stmt>,
     },
     TypeRef(TypeDef),
     List(Vec<Value>),
-    Module {
-        tools: HashMap<String, ToolDef>,
-        structs: HashMap<String, TypeDef>,
-        templates: HashMap<String, TypeDef>,
-    },
+    Map(IndexMap<MapKey, Value>),
+    // boxed to keep every other Value small
+    Module(Box<ModuleExports>),
+}
+
+/// Keys allowed in a map literal; other values have no stable identity to hash.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Int(i64),
+    String(String),
+}
+
+impl MapKey {
+    pub fn from_value(value: Value) -> Result<MapKey, RuntimeError> {
+        match value {
+            Value::Int(n) => Ok(MapKey::Int(n)),
+            Value::String(s) => Ok(MapKey::String(s)),
+            other => Err(RuntimeError::TypeMismatch {
+                expected: "Int or String map key".to_string(),
+                actual: other.type_name().to_string(),
+            }),
+        }
+    }
+
+    pub fn into_value(self) -> Value {
+        match self {
+            MapKey::Int(n) => Value::Int(n),
+            MapKey::String(s) => Value::String(s),
+        }
+    }
+}
+
+/// Hashable snapshot of an argument for `memo`; tools, types and modules have none.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MemoKey {
+    Int(i64),
+    Float(u64),
+    String(String),
+    Char(char),
+    Bool(bool),
+    Null,
+    List(Vec<MemoKey>),
+    Object(String, Vec<(String, MemoKey)>),
+    Map(Vec<(MapKey, MemoKey)>),
 }
 
+impl MemoKey {
+    pub fn from_value(value: &Value) -> Option<MemoKey> {
+        Some(match value {
+            Value::Int(n) => MemoKey::Int(*n),
+            Value::Float(f) => MemoKey::Float(f.to_bits()),
+            Value::String(s) => MemoKey::String(s.clone()),
+            Value::Char(c) => MemoKey::Char(*c),
+            Value::Bool(b) => MemoKey::Bool(*b),
+            Value::Null => MemoKey::Null,
+            Value::List(items) => MemoKey::List(
+                items
+                    .iter()
+                    .map(MemoKey::from_value)
+                    .collect::<Option<_>>()?,
+            ),
+            Value::Object {
+                type_name, fields, ..
+            } => MemoKey::Object(
+                type_name.clone(),
+                fields
+                    .iter()
+                    .map(|(name, value)| Some((name.clone(), MemoKey::from_value(value)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Value::Map(entries) => MemoKey::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| Some((key.clone(), MemoKey::from_value(value)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Value::ToolRef { .. } | Value::TypeRef(_) | Value::Module(_) => return None,
+        })
+    }
+}
+
+// always shows a `.` or an exponent so a Float never reads as an Int: 3.0, 0.1, 1e20, 1e-7
+fn format_float(n: f64) -> String {
+    if n.is_nan() {
+        "nan".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        // Debug is the shortest round-tripping form, switching to an exponent for
+        // very large or very small magnitudes
+        format!("{:?}", n)
+    }
+}
+
+// inverse of lexer::unescape, so displayed strings and chars read back as the same literal
+fn escape(text: &str, quote: char) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            // keep a literal `${` from turning into an interpolation
+            '$' if quote == '"' && chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapKey::Int(n) => write!(f, "{}", n),
+            MapKey::String(s) => write!(f, "\"{}\"", escape(s, '"')),
+        }
+    }
+}
+
+/// Output is stable from run to run: object fields follow the struct declaration,
+/// then any extra fields in the order they were set, and maps keep insertion order.
+///
+/// ```
+/// use loquora::eval;
+///
+/// let point = eval("struct P { x: Int, y: Int, } P { y: 2, x: 1 };").unwrap();
+/// assert_eq!(point.to_string(), "P { x: 1, y: 2 }");
+/// let map = eval("#{ \"b\": 1, \"a\": 2 };").unwrap();
+/// assert_eq!(map.to_string(), "#{\"b\": 1, \"a\": 2}");
+/// ```
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Int(n) => write!(f, "{}", n),
-            Value::Float(n) => write!(f, "{}", n),
-            Value::String(s) => write!(f, "\"{}\"", s),
-            Value::Char(c) => write!(f, "'{}'", c),
+            Value::Float(n) => write!(f, "{}", format_float(*n)),
+            Value::String(s) => write!(f, "\"{}\"", escape(s, '"')),
+            Value::Char(c) => write!(f, "'{}'", escape(&c.to_string(), '\'')),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Null => write!(f, "null"),
-            Value::Object { type_name, fields } => {
+            Value::Object {
+                type_name, fields, ..
+            } => {
                 write!(f, "{} {{ ", type_name)?;
                 let mut first = true;
                 for (key, value) in fields {
@@ -67,18 +211,35 @@ impl fmt::Display for Value {
                 }
                 write!(f, "]")
             }
-            Value::Module {
-                tools,
-                structs,
-                templates,
-            } => {
-                write!(
-                    f,
-                    "module<{} tools, {} structs, {} templates>",
-                    tools.len(),
-                    structs.len(),
-                    templates.len()
-                )
+            Value::Map(entries) => {
+                write!(f, "#{{")?;
+                let mut first = true;
+                for (key, value) in entries {
+                    if !first {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                    first = false;
+                }
+                write!(f, "}}")
+            }
+            Value::Module(exports) => {
+                let ModuleExports {
+                    tools,
+                    structs,
+                    templates,
+                } = exports.as_ref();
+                // names in export order; groups with nothing exported are left out
+                let groups: Vec<String> = [
+                    ("tools", tools.keys().cloned().collect::<Vec<_>>()),
+                    ("structs", structs.keys().cloned().collect()),
+                    ("templates", templates.keys().cloned().collect()),
+                ]
+                .into_iter()
+                .filter(|(_, names)| !names.is_empty())
+                .map(|(group, names)| format!("{}: {}", group, names.join(", ")))
+                .collect();
+                write!(f, "module<{}>", groups.join("; "))
             }
         }
     }
@@ -87,12 +248,32 @@ impl fmt::Display for Value {
 #[derive(Debug, Clone)]
 pub enum RuntimeError {
     UndefinedVariable(String),
+    UndeclaredAssignment(String),
     UndefinedTool(String),
-    TypeMismatch { expected: String, actual: String },
+    TypeMismatch {
+        expected: String,
+        actual: String,
+    },
+    /// A Float straight from `/` where an Int is declared, a spot where `/` used to truncate.
+    /// Other Floats in an Int slot are a plain [`RuntimeError::TypeMismatch`].
+    ///
+    /// ```
+    /// use loquora::eval;
+    ///
+    /// let halved = eval("tool half(n: Int) -> Int { return n / 2; } half(8);");
+    /// assert!(halved.unwrap_err().to_string().contains("use `~/` or div()"));
+    ///
+    /// let ranged = eval("range(0, 2.5);").unwrap_err().to_string();
+    /// assert_eq!(ranged, "Runtime error: Type mismatch: expected Int, got Float");
+    /// ```
+    FloatDivision {
+        expected: String,
+    },
     FieldNotFound(String),
     RequiredFieldMissing(String),
+    DuplicateDefinition(String),
     NotAnObject,
-    NotCallable,
+    NotCallable(String),
     InvalidArguments(String),
     DivisionByZero,
     BreakOutsideLoop,
@@ -106,16 +287,29 @@ impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             RuntimeError::UndefinedVariable(name) => write!(f, "Undefined variable: {}", name),
+            RuntimeError::UndeclaredAssignment(name) => write!(
+                f,
+                "Cannot assign to undeclared variable: {} (declare it with `let {} = ...`)",
+                name, name
+            ),
             RuntimeError::UndefinedTool(name) => write!(f, "Undefined tool: {}", name),
             RuntimeError::TypeMismatch { expected, actual } => {
                 write!(f, "Type mismatch: expected {}, got {}", expected, actual)
             }
+            RuntimeError::FloatDivision { expected } => write!(
+                f,
+                "Type mismatch: expected {}, got Float (`/` always returns Float; use `~/` or div() for integer division)",
+                expected
+            ),
             RuntimeError::FieldNotFound(name) => write!(f, "Field not found: {}", name),
             RuntimeError::RequiredFieldMissing(name) => {
                 write!(f, "Required field missing: {}", name)
             }
+            RuntimeError::DuplicateDefinition(name) => {
+                write!(f, "{} is already defined", name)
+            }
             RuntimeError::NotAnObject => write!(f, "Value is not an object"),
-            RuntimeError::NotCallable => write!(f, "Value is not callable"),
+            RuntimeError::NotCallable(callee) => write!(f, "Value is not callable: {}", callee),
             RuntimeError::InvalidArguments(msg) => write!(f, "Invalid arguments: {}", msg),
             RuntimeError::DivisionByZero => write!(f, "Division by zero"),
             RuntimeError::BreakOutsideLoop => write!(f, "Break statement outside of loop"),
@@ -138,37 +332,75 @@ impl Value {
                 .get(name)
                 .cloned()
                 .ok_or_else(|| RuntimeError::FieldNotFound(name.to_string())),
-            Value::Module {
-                tools,
-                structs,
-                templates,
-            } => {
-                if let Some(tool) = tools.get(name) {
+            Value::Module(exports) => {
+                if let Some(tool) = exports.tools.get(name) {
                     Ok(Value::ToolRef {
                         name: tool.name.clone(),
                         params: tool.params.clone(),
+                        return_type: tool.return_type.clone(),
                         body: tool.body.clone(),
                     })
-                } else if let Some(struct_def) = structs.get(name) {
+                } else if let Some(struct_def) = exports.structs.get(name) {
                     Ok(Value::TypeRef(struct_def.clone()))
-                } else if let Some(template_def) = templates.get(name) {
+                } else if let Some(template_def) = exports.templates.get(name) {
                     Ok(Value::TypeRef(template_def.clone()))
                 } else {
                     Err(RuntimeError::FieldNotFound(name.to_string()))
                 }
             }
+            Value::String(s) => match name {
+                "length" => Ok(Value::Int(s.chars().count() as i64)),
+                _ => Err(RuntimeError::FieldNotFound(format!("{} on String", name))),
+            },
+            Value::List(items) => match name {
+                "length" => Ok(Value::Int(items.len() as i64)),
+                "first" => Ok(items.first().cloned().unwrap_or(Value::Null)),
+                "last" => Ok(items.last().cloned().unwrap_or(Value::Null)),
+                _ => Err(RuntimeError::FieldNotFound(format!("{} on List", name))),
+            },
             _ => Err(RuntimeError::NotAnObject),
         }
     }
 
     pub fn set_property(&self, name: &str, value: Value) -> Result<Value, RuntimeError> {
         match self {
-            Value::Object { type_name, fields } => {
+            Value::Object {
+                type_name,
+                fields,
+                members,
+            } => {
                 let mut new_fields = fields.clone();
                 new_fields.insert(name.to_string(), value);
                 Ok(Value::Object {
                     type_name: type_name.clone(),
                     fields: new_fields,
+                    members: members.clone(),
+                })
+            }
+            _ => Err(RuntimeError::NotAnObject),
+        }
+    }
+
+    pub fn has_field(&self, name: &str) -> Result<bool, RuntimeError> {
+        match self {
+            Value::Object { fields, .. } => Ok(fields.contains_key(name)),
+            _ => Err(RuntimeError::NotAnObject),
+        }
+    }
+
+    pub fn remove_field(&self, name: &str) -> Result<Value, RuntimeError> {
+        match self {
+            Value::Object {
+                type_name,
+                fields,
+                members,
+            } => {
+                let mut new_fields = fields.clone();
+                new_fields.shift_remove(name);
+                Ok(Value::Object {
+                    type_name: type_name.clone(),
+                    fields: new_fields,
+                    members: members.clone(),
                 })
             }
             _ => Err(RuntimeError::NotAnObject),
@@ -187,7 +419,48 @@ impl Value {
             Value::ToolRef { .. } => "Tool",
             Value::TypeRef(_) => "Type",
             Value::List(_) => "List",
-            Value::Module { .. } => "Module",
+            Value::Map(_) => "Map",
+            Value::Module(_) => "Module",
+        }
+    }
+
+    /// Like `type_name`, but objects report their struct name.
+    pub fn type_label(&self) -> String {
+        match self {
+            Value::Object { type_name, .. } => type_name.clone(),
+            other => other.type_name().to_string(),
+        }
+    }
+
+    /// Like `type_label`, but tools also report their name, as in `tool<greet>`.
+    pub fn type_of(&self) -> String {
+        match self {
+            Value::ToolRef { name, .. } => format!("tool<{}>", name),
+            other => other.type_label(),
+        }
+    }
+
+    /// Checks a value against a declared type. `Any` matches everything, `Float`
+    /// accepts `Int`, `T?` also accepts null, and generic types only check the
+    /// outer name. A name that is neither built in nor `declared` (a struct or
+    /// type in scope) is not checked at all.
+    pub fn matches_type(&self, ty: &TypeExprKind, declared: &dyn Fn(&str) -> bool) -> bool {
+        if let TypeExprKind::Optional(inner) = ty {
+            return matches!(self, Value::Null) || self.matches_type(&inner.inner, declared);
+        }
+        let expected = ty.base_name();
+        // module paths like `geo.Rectangle` always name a declaration
+        if !BUILTIN_TYPES.contains(&expected) && !expected.contains('.') && !declared(expected) {
+            return true;
+        }
+        match (expected, self) {
+            ("Any", _) => true,
+            ("Float", Value::Int(_)) => true,
+            // objects carry only the bare struct name, so `geo.Rectangle` matches `Rectangle`
+            (_, Value::Object { type_name, .. }) => {
+                expected == "Object" || expected.rsplit('.').next() == Some(type_name.as_str())
+            }
+            _ => expected == self.type_name(),
         }
     }
 
@@ -199,6 +472,7 @@ impl Value {
             Value::Float(f) if *f == 0.0 => false,
             Value::String(s) if s.is_empty() => false,
             Value::List(items) if items.is_empty() => false,
+            Value::Map(entries) if entries.is_empty() => false,
             _ => true,
         }
     }
@@ -227,6 +501,7 @@ impl Value {
             Value::Float(f) => Ok(*f),
             Value::Bool(true) => Ok(1.0),
             Value::Bool(false) => Ok(0.0),
+            Value::Char(c) => Ok(*c as u32 as f64),
             Value::String(s) => s.parse::<f64>().map_err(|_| RuntimeError::TypeMismatch {
                 expected: "Float or numeric string".to_string(),
                 actual: format!("String(\"{}\")", s),
@@ -242,9 +517,12 @@ impl Value {
         self.is_truthy()
     }
 
-    pub fn as_string(&self) -> String {
+    /// User-facing rendering: a top-level string or char is written bare, while
+    /// lists and objects keep the quoted `Display` form for their contents.
+    pub fn to_display_string(&self) -> String {
         match self {
             Value::String(s) => s.clone(),
+            Value::Char(c) => c.to_string(),
             _ => format!("{}", self),
         }
     }
@@ -0,0 +1,828 @@
+use crate::loquora::ast::*;
+use crate::loquora::interpreter::{DEFAULT_MAX_CALL_DEPTH, Interpreter};
+use crate::loquora::token::{Span, TokenKind};
+use crate::loquora::value::{RuntimeError, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+// jump targets are absolute indices into the enclosing instruction list
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    Const(Value),
+    Load(String),
+    // `name = value` at the top level; the name has to be bound already
+    Store(String),
+    // `let name = value` at the top level
+    Define(String),
+    // a tool's parameters and assigned names live in numbered slots instead of the
+    // environment; an unset slot reads through to the environment by name
+    LoadLocal {
+        slot: usize,
+        name: String,
+    },
+    StoreLocal(usize),
+    // assignment inside a tool, which fails on a slot nothing has bound yet
+    AssignLocal {
+        slot: usize,
+        name: String,
+    },
+    Pop,
+    Dup,
+    Binary(TokenKind),
+    Unary(TokenKind),
+    Property(String),
+    Jump(usize),
+    // pops the condition
+    JumpIfFalse(usize),
+    JumpIfTrue(usize),
+    // leaves the value in place; used by `??` / `?:`
+    JumpIfNotNull(usize),
+    // callee and arguments are on the stack, callee first; `divisions` marks the
+    // arguments written as `a / b`, for the hint on an Int mismatch
+    Call {
+        argc: usize,
+        span: Span,
+        divisions: Vec<bool>,
+    },
+    // `name(args)` where name is resolved when the call runs, skipping the ToolRef
+    CallNamed {
+        name: String,
+        argc: usize,
+        span: Span,
+        divisions: Vec<bool>,
+    },
+    Return,
+    // `return a / b`, kept apart for the same hint
+    ReturnDivision,
+    // binds the tool at this index in `CompiledProgram::tools`, at the point it's declared
+    DefineTool(usize),
+    // bracket one loop iteration at the top level, so names it binds go away with it
+    PushScope,
+    PopScope,
+    // the same inside a tool: slots still unset when the iteration starts are unset again
+    // once it ends
+    MarkLocals,
+    ResetLocals,
+}
+
+#[derive(Debug)]
+pub struct CompiledTool {
+    pub name: String,
+    pub params: Vec<ParamDecl>,
+    pub return_type: Option<TypeExpr>,
+    // kept so the tool can still be passed around as an ordinary ToolRef
+    pub body: Vec<Stmt>,
+    pub code: Vec<Instruction>,
+    // parameters first, in order
+    pub slots: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct CompiledProgram {
+    pub main: Vec<Instruction>,
+    pub tools: Vec<Rc<CompiledTool>>,
+}
+
+/// Compiles a program for [`Vm`]. Only top-level tools, assignments to plain names,
+/// `if`, `while`, `do`/`while`, `loop` and expressions without literals for objects,
+/// maps or interpolated strings are supported; anything else is reported as an error
+/// so the caller can run the program on the tree-walking [`Interpreter`] instead.
+pub fn compile(program: &Program) -> Result<CompiledProgram, RuntimeError> {
+    let mut tools = Vec::new();
+    let mut compiler = Compiler::new(&mut tools, None);
+    // the program's result is its final expression statement, as in the interpreter
+    match program.statements.split_last() {
+        Some((
+            Stmt {
+                inner: StmtKind::ExprStmt { expr },
+                ..
+            },
+            rest,
+        )) => {
+            compiler.stmts(rest)?;
+            compiler.expr(expr)?;
+        }
+        _ => {
+            compiler.stmts(&program.statements)?;
+            compiler.emit(Instruction::Const(Value::Null));
+        }
+    }
+    compiler.emit(Instruction::Return);
+    let main = compiler.code;
+    Ok(CompiledProgram { main, tools })
+}
+
+#[derive(Default)]
+struct LoopPatches {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+struct Compiler<'a> {
+    code: Vec<Instruction>,
+    loops: Vec<LoopPatches>,
+    // > 0 inside if / loop bodies, where the interpreter scopes declarations differently
+    block_depth: usize,
+    tools: &'a mut Vec<Rc<CompiledTool>>,
+    // slot names of the tool being compiled; None at the top level
+    locals: Option<Vec<String>>,
+    // names the tool declares more than once, counting parameters
+    redeclared: Vec<String>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(tools: &'a mut Vec<Rc<CompiledTool>>, locals: Option<Vec<String>>) -> Self {
+        Compiler {
+            code: Vec::new(),
+            loops: Vec::new(),
+            block_depth: 0,
+            tools,
+            locals,
+            redeclared: Vec::new(),
+        }
+    }
+
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.locals
+            .as_ref()
+            .and_then(|locals| locals.iter().position(|local| local == name))
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.code.push(instruction);
+        self.code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        match &mut self.code[at] {
+            Instruction::Jump(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::JumpIfTrue(to)
+            | Instruction::JumpIfNotNull(to) => *to = target,
+            other => unreachable!("patching a non-jump instruction {:?}", other),
+        }
+    }
+
+    fn unsupported(what: &str) -> RuntimeError {
+        RuntimeError::Custom(format!(
+            "the bytecode compiler does not support {} yet",
+            what
+        ))
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        self.block_depth += 1;
+        let result = self.stmts(stmts);
+        self.block_depth -= 1;
+        result
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        match &stmt.inner {
+            StmtKind::ExprStmt { expr } => {
+                self.expr(expr)?;
+                self.emit(Instruction::Pop);
+            }
+            StmtKind::Assignment { target, value } => {
+                let [name] = target.as_slice() else {
+                    return Err(Self::unsupported("assigning to a field"));
+                };
+                self.expr(value)?;
+                match self.slot(name) {
+                    Some(slot) => self.emit(Instruction::AssignLocal {
+                        slot,
+                        name: name.clone(),
+                    }),
+                    None => self.emit(Instruction::Store(name.clone())),
+                };
+            }
+            StmtKind::Let { name, value } => {
+                // slots can't hold an inner binding and the outer one it hides at once
+                if !self.loops.is_empty() && self.redeclared.contains(name) {
+                    return Err(Self::unsupported("`let` shadowing a local inside a loop"));
+                }
+                self.expr(value)?;
+                match self.slot(name) {
+                    Some(slot) => self.emit(Instruction::StoreLocal(slot)),
+                    None => self.emit(Instruction::Define(name.clone())),
+                };
+            }
+            StmtKind::If { arms, else_body } => {
+                let mut ends = Vec::new();
+                for (cond, body) in arms {
+                    self.expr(cond)?;
+                    let skip = self.emit(Instruction::JumpIfFalse(0));
+                    self.block(body)?;
+                    ends.push(self.emit(Instruction::Jump(0)));
+                    let next = self.code.len();
+                    self.patch(skip, next);
+                }
+                if let Some(else_body) = else_body {
+                    self.block(else_body)?;
+                }
+                let end = self.code.len();
+                for at in ends {
+                    self.patch(at, end);
+                }
+            }
+            StmtKind::While {
+                cond,
+                body,
+                else_body,
+            } => {
+                let start = self.code.len();
+                self.expr(cond)?;
+                let exit = self.emit(Instruction::JumpIfFalse(0));
+                let patches = self.loop_body(body)?;
+                self.emit(Instruction::Jump(start));
+                // falling out through the condition runs the else block, `break` skips it
+                let after = self.code.len();
+                self.patch(exit, after);
+                if let Some(else_body) = else_body {
+                    self.block(else_body)?;
+                }
+                self.finish_loop(patches, start);
+            }
+            StmtKind::DoWhile { body, cond } => {
+                let start = self.code.len();
+                let patches = self.loop_body(body)?;
+                let check = self.code.len();
+                self.expr(cond)?;
+                self.emit(Instruction::JumpIfTrue(start));
+                self.finish_loop(patches, check);
+            }
+            StmtKind::Loop { body } => {
+                let start = self.code.len();
+                let patches = self.loop_body(body)?;
+                self.emit(Instruction::Jump(start));
+                self.finish_loop(patches, start);
+            }
+            StmtKind::Break { expr: Some(_) } => {
+                return Err(Self::unsupported("break with a value"));
+            }
+            StmtKind::Break { expr: None } => {
+                self.emit(self.exit_iteration());
+                let at = self.emit(Instruction::Jump(0));
+                match self.loops.last_mut() {
+                    Some(patches) => patches.breaks.push(at),
+                    None => return Err(RuntimeError::BreakOutsideLoop),
+                }
+            }
+            StmtKind::Continue => {
+                self.emit(self.exit_iteration());
+                let at = self.emit(Instruction::Jump(0));
+                match self.loops.last_mut() {
+                    Some(patches) => patches.continues.push(at),
+                    None => return Err(RuntimeError::ContinueOutsideLoop),
+                }
+            }
+            StmtKind::Return { expr } => {
+                if self.locals.is_none() {
+                    return Err(RuntimeError::ReturnOutsideFunction);
+                }
+                match expr {
+                    Some(expr) => self.expr(expr)?,
+                    None => {
+                        self.emit(Instruction::Const(Value::Null));
+                    }
+                }
+                if expr.as_ref().is_some_and(Interpreter::is_division) {
+                    self.emit(Instruction::ReturnDivision);
+                } else {
+                    self.emit(Instruction::Return);
+                }
+            }
+            StmtKind::ToolDecl {
+                name,
+                params,
+                return_type,
+                body,
+            } => {
+                if self.locals.is_some() || self.block_depth > 0 {
+                    return Err(Self::unsupported("tools declared inside a block"));
+                }
+                if params.iter().any(|param| param.default.is_some()) {
+                    return Err(Self::unsupported("default parameter values"));
+                }
+                let mut locals: Vec<String> = params.iter().map(|p| p.name.clone()).collect();
+                let mut declared = locals.clone();
+                assigned_names(body, &mut locals, &mut declared);
+                let slots = locals.len();
+                let mut compiler = Compiler::new(self.tools, Some(locals));
+                for (i, name) in declared.iter().enumerate() {
+                    if declared[..i].contains(name) && !compiler.redeclared.contains(name) {
+                        compiler.redeclared.push(name.clone());
+                    }
+                }
+                compiler.stmts(body)?;
+                compiler.emit(Instruction::Const(Value::Null));
+                compiler.emit(Instruction::Return);
+                let code = compiler.code;
+                let tool = CompiledTool {
+                    name: name.clone(),
+                    params: params.clone(),
+                    return_type: return_type.clone(),
+                    body: body.clone(),
+                    code,
+                    slots,
+                };
+                self.tools.push(Rc::new(tool));
+                self.emit(Instruction::DefineTool(self.tools.len() - 1));
+            }
+            StmtKind::For { .. } => return Err(Self::unsupported("for loops")),
+            StmtKind::Match { .. } => return Err(Self::unsupported("match statements")),
+            StmtKind::Destructure { .. } => {
+                return Err(Self::unsupported("destructuring assignment"));
+            }
+            StmtKind::With { .. } => return Err(Self::unsupported("with blocks")),
+            StmtKind::StructDecl { .. } | StmtKind::TemplateDecl { .. } => {
+                return Err(Self::unsupported("type declarations"));
+            }
+            StmtKind::Load { .. } | StmtKind::LoadAndRun { .. } => {
+                return Err(Self::unsupported("loading modules"));
+            }
+            StmtKind::ExportDecl { .. } | StmtKind::ExportBlock { .. } => {
+                return Err(Self::unsupported("exports"));
+            }
+        }
+        Ok(())
+    }
+
+    fn loop_body(&mut self, body: &[Stmt]) -> Result<LoopPatches, RuntimeError> {
+        self.loops.push(LoopPatches::default());
+        self.emit(self.enter_iteration());
+        let result = self.block(body);
+        self.emit(self.exit_iteration());
+        let patches = self.loops.pop().unwrap_or_default();
+        result.map(|()| patches)
+    }
+
+    fn enter_iteration(&self) -> Instruction {
+        match self.locals {
+            Some(_) => Instruction::MarkLocals,
+            None => Instruction::PushScope,
+        }
+    }
+
+    // also emitted ahead of `break` / `continue`, which jump past the iteration's end
+    fn exit_iteration(&self) -> Instruction {
+        match self.locals {
+            Some(_) => Instruction::ResetLocals,
+            None => Instruction::PopScope,
+        }
+    }
+
+    fn finish_loop(&mut self, patches: LoopPatches, continue_target: usize) {
+        let end = self.code.len();
+        for at in patches.breaks {
+            self.patch(at, end);
+        }
+        for at in patches.continues {
+            self.patch(at, continue_target);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), RuntimeError> {
+        match &expr.inner {
+            ExprKind::Int(n) => {
+                self.emit(Instruction::Const(Value::Int(*n)));
+            }
+            ExprKind::Float(f) => {
+                self.emit(Instruction::Const(Value::Float(*f)));
+            }
+            ExprKind::String(s) => {
+                self.emit(Instruction::Const(Value::String(s.clone())));
+            }
+            ExprKind::Char(c) => {
+                self.emit(Instruction::Const(Value::Char(*c)));
+            }
+            ExprKind::Bool(b) => {
+                self.emit(Instruction::Const(Value::Bool(*b)));
+            }
+            ExprKind::Null => {
+                self.emit(Instruction::Const(Value::Null));
+            }
+            ExprKind::Identifier(name) => {
+                match self.slot(name) {
+                    Some(slot) => self.emit(Instruction::LoadLocal {
+                        slot,
+                        name: name.clone(),
+                    }),
+                    None => self.emit(Instruction::Load(name.clone())),
+                };
+            }
+            // && and || keep the deciding operand, like the interpreter
+            ExprKind::BinaryOp { op, left, right }
+                if matches!(op, TokenKind::LogicalAnd | TokenKind::LogicalOr) =>
+            {
+                self.expr(left)?;
+                self.emit(Instruction::Dup);
+                let decided = if *op == TokenKind::LogicalAnd {
+                    self.emit(Instruction::JumpIfFalse(0))
+                } else {
+                    self.emit(Instruction::JumpIfTrue(0))
+                };
+                self.emit(Instruction::Pop);
+                self.expr(right)?;
+                let end = self.code.len();
+                self.patch(decided, end);
+            }
+            ExprKind::BinaryOp { op, left, right } => {
+                self.expr(left)?;
+                self.expr(right)?;
+                self.emit(Instruction::Binary(op.clone()));
+            }
+            ExprKind::UnaryOp { op, expr } => {
+                self.expr(expr)?;
+                self.emit(Instruction::Unary(op.clone()));
+            }
+            ExprKind::Ternary {
+                cond,
+                if_true,
+                if_false,
+            } => {
+                self.expr(cond)?;
+                let otherwise = self.emit(Instruction::JumpIfFalse(0));
+                self.expr(if_true)?;
+                let done = self.emit(Instruction::Jump(0));
+                let else_start = self.code.len();
+                self.patch(otherwise, else_start);
+                self.expr(if_false)?;
+                let end = self.code.len();
+                self.patch(done, end);
+            }
+            ExprKind::Coalesce { value, fallback } => {
+                self.expr(value)?;
+                let present = self.emit(Instruction::JumpIfNotNull(0));
+                self.emit(Instruction::Pop);
+                self.expr(fallback)?;
+                let end = self.code.len();
+                self.patch(present, end);
+            }
+            ExprKind::Call { callee, args } => {
+                if args
+                    .iter()
+                    .any(|arg| matches!(arg.inner, ExprKind::Spread(_)))
+                {
+                    return Err(Self::unsupported("spread arguments"));
+                }
+                let named = match &callee.inner {
+                    ExprKind::Identifier(name) if self.slot(name).is_none() => Some(name.clone()),
+                    _ => {
+                        self.expr(callee)?;
+                        None
+                    }
+                };
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                let argc = args.len();
+                let span = expr.span.clone();
+                let divisions = args.iter().map(Interpreter::is_division).collect();
+                match named {
+                    Some(name) => self.emit(Instruction::CallNamed {
+                        name,
+                        argc,
+                        span,
+                        divisions,
+                    }),
+                    None => self.emit(Instruction::Call {
+                        argc,
+                        span,
+                        divisions,
+                    }),
+                };
+            }
+            ExprKind::Property { object, property } => {
+                self.expr(object)?;
+                self.emit(Instruction::Property(property.clone()));
+            }
+            ExprKind::Interpolation(_) => return Err(Self::unsupported("string interpolation")),
+            ExprKind::ComparisonChain { .. } => {
+                return Err(Self::unsupported("chained comparisons"));
+            }
+            ExprKind::Quaternary { .. } => return Err(Self::unsupported("quaternary expressions")),
+            ExprKind::ObjectInit { .. } => return Err(Self::unsupported("object literals")),
+            ExprKind::MapLiteral { .. } => return Err(Self::unsupported("map literals")),
+            ExprKind::Loop { .. } => return Err(Self::unsupported("loop expressions")),
+            ExprKind::IfExpr { .. } => return Err(Self::unsupported("if expressions")),
+            ExprKind::ListLiteral { .. } => return Err(Self::unsupported("list literals")),
+            ExprKind::Spread(_) => return Err(Self::unsupported("spread arguments")),
+        }
+        Ok(())
+    }
+}
+
+// names a tool body declares or assigns to directly, which become its local slots;
+// `declared` gets one entry per `let`, repeats included
+fn assigned_names(stmts: &[Stmt], names: &mut Vec<String>, declared: &mut Vec<String>) {
+    for stmt in stmts {
+        match &stmt.inner {
+            StmtKind::Assignment { target, .. } => {
+                if let [name] = target.as_slice()
+                    && !names.contains(name)
+                {
+                    names.push(name.clone());
+                }
+            }
+            StmtKind::Let { name, .. } => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+                declared.push(name.clone());
+            }
+            StmtKind::If { arms, else_body } => {
+                for (_, body) in arms {
+                    assigned_names(body, names, declared);
+                }
+                if let Some(else_body) = else_body {
+                    assigned_names(else_body, names, declared);
+                }
+            }
+            StmtKind::While {
+                body, else_body, ..
+            } => {
+                assigned_names(body, names, declared);
+                if let Some(else_body) = else_body {
+                    assigned_names(else_body, names, declared);
+                }
+            }
+            StmtKind::DoWhile { body, .. } | StmtKind::Loop { body } => {
+                assigned_names(body, names, declared);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A stack machine running [`CompiledProgram`]s. Globals, operators and builtins live
+/// in an ordinary [`Interpreter`], so values, results and error messages match, and
+/// tools handed to builtins such as `sort` or `memo` can call back into the program.
+///
+/// Inside a tool, names it never assigns resolve to globals, tools and builtins; unlike
+/// the interpreter, the locals of whichever tool called it are not visible.
+///
+/// ```
+/// use loquora::loquora::vm::{self, Vm};
+/// use loquora::loquora::{lexer::Lexer, parser::Parser};
+/// use loquora::Value;
+///
+/// let source = "tool sq(n: Int) -> Int { return n * n; } let total = 0; let i = 1; \
+///               while i <= 3 { total = total + sq(i); i = i + 1; } total;";
+/// let program = Parser::new(Lexer::new(source.to_string())).parse_program();
+/// let compiled = vm::compile(&program).unwrap();
+/// assert!(matches!(Vm::new().run(&compiled), Ok(Value::Int(14))));
+/// ```
+pub struct Vm {
+    runtime: Interpreter,
+    // the compiled form of each top-level tool currently bound in the runtime
+    defined: HashMap<String, Rc<CompiledTool>>,
+    depth: usize,
+    max_call_depth: usize,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm {
+            runtime: Interpreter::new(),
+            defined: HashMap::new(),
+            depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.runtime.set_output(output);
+    }
+
+    pub fn set_allow_shadowing(&mut self, allow: bool) {
+        self.runtime.set_allow_shadowing(allow);
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+        self.runtime.set_max_call_depth(depth);
+    }
+
+    pub fn run(&mut self, program: &CompiledProgram) -> Result<Value, RuntimeError> {
+        let result = self.execute(program, &program.main, &mut []);
+        if result.is_err() {
+            self.runtime.env.unwind_to_global();
+            self.depth = 0;
+        }
+        result
+    }
+
+    fn execute(
+        &mut self,
+        program: &CompiledProgram,
+        code: &[Instruction],
+        locals: &mut [Option<Value>],
+    ) -> Result<Value, RuntimeError> {
+        let mut stack: Vec<Value> = Vec::new();
+        // unset slots recorded by each enclosing MarkLocals
+        let mut marks: Vec<Vec<usize>> = Vec::new();
+        let mut ip = 0;
+        while let Some(instruction) = code.get(ip) {
+            ip += 1;
+            match instruction {
+                Instruction::Const(value) => stack.push(value.clone()),
+                Instruction::Load(name) => stack.push(self.runtime.env.get(name)?),
+                Instruction::LoadLocal { slot, name } => match &locals[*slot] {
+                    Some(value) => stack.push(value.clone()),
+                    None => stack.push(self.runtime.env.get(name)?),
+                },
+                Instruction::StoreLocal(slot) => locals[*slot] = Some(pop(&mut stack)),
+                Instruction::Store(name) => {
+                    let value = pop(&mut stack);
+                    self.runtime.env.assign(name, value)?;
+                }
+                Instruction::Define(name) => {
+                    let value = pop(&mut stack);
+                    self.runtime.env.set(name, value);
+                }
+                Instruction::AssignLocal { slot, name } => match &mut locals[*slot] {
+                    Some(local) => *local = pop(&mut stack),
+                    None => return Err(self.runtime.env.unassignable(name)),
+                },
+                Instruction::Pop => {
+                    pop(&mut stack);
+                }
+                Instruction::Dup => {
+                    let top = stack.last().cloned().unwrap_or(Value::Null);
+                    stack.push(top);
+                }
+                Instruction::Binary(op) => {
+                    let right = pop(&mut stack);
+                    let left = pop(&mut stack);
+                    stack.push(self.runtime.apply_binary_op(op, left, right)?);
+                }
+                Instruction::Unary(op) => {
+                    let value = pop(&mut stack);
+                    stack.push(Interpreter::apply_unary_op(op, value)?);
+                }
+                Instruction::Property(name) => {
+                    let object = pop(&mut stack);
+                    stack.push(object.get_property(name)?);
+                }
+                Instruction::Jump(target) => ip = *target,
+                Instruction::JumpIfFalse(target) => {
+                    if !pop(&mut stack).is_truthy() {
+                        ip = *target;
+                    }
+                }
+                Instruction::JumpIfTrue(target) => {
+                    if pop(&mut stack).is_truthy() {
+                        ip = *target;
+                    }
+                }
+                Instruction::JumpIfNotNull(target) => {
+                    if !matches!(stack.last(), Some(Value::Null) | None) {
+                        ip = *target;
+                    }
+                }
+                Instruction::Call {
+                    argc,
+                    span,
+                    divisions,
+                } => {
+                    let args = stack.split_off(stack.len() - argc);
+                    let callee = pop(&mut stack);
+                    stack.push(self.call(program, callee, args, span, divisions)?);
+                }
+                Instruction::CallNamed {
+                    name,
+                    argc,
+                    span,
+                    divisions,
+                } => {
+                    let args = stack.split_off(stack.len() - argc);
+                    // a variable of the same name wins over the tool, as in the interpreter
+                    let result = match self.defined.get(name) {
+                        Some(tool) if !self.runtime.env.has_variable(name) => {
+                            self.call_compiled(program, tool.clone(), args, divisions)?
+                        }
+                        _ => {
+                            let callee =
+                                self.runtime.env.get(name).map_err(|error| match error {
+                                    RuntimeError::UndefinedVariable(_) => {
+                                        RuntimeError::UndefinedTool(name.clone())
+                                    }
+                                    other => other,
+                                })?;
+                            if !matches!(callee, Value::ToolRef { .. }) {
+                                return Err(RuntimeError::NotCallable(format!(
+                                    "{} is {}",
+                                    name,
+                                    callee.type_name()
+                                )));
+                            }
+                            self.call(program, callee, args, span, divisions)?
+                        }
+                    };
+                    stack.push(result);
+                }
+                Instruction::Return | Instruction::ReturnDivision => {
+                    self.runtime.returned_division =
+                        matches!(instruction, Instruction::ReturnDivision);
+                    return Ok(pop(&mut stack));
+                }
+                Instruction::DefineTool(index) => {
+                    let tool = program.tools[*index].clone();
+                    self.runtime.env.define_tool(
+                        tool.name.clone(),
+                        tool.params.clone(),
+                        tool.return_type.clone(),
+                        tool.body.clone(),
+                    )?;
+                    self.defined.insert(tool.name.clone(), tool);
+                }
+                Instruction::PushScope => self.runtime.env.push_scope(),
+                Instruction::PopScope => self.runtime.env.pop_scope(),
+                Instruction::MarkLocals => marks.push(
+                    (0..locals.len())
+                        .filter(|&slot| locals[slot].is_none())
+                        .collect(),
+                ),
+                Instruction::ResetLocals => {
+                    for slot in marks.pop().unwrap_or_default() {
+                        locals[slot] = None;
+                    }
+                }
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    fn call(
+        &mut self,
+        program: &CompiledProgram,
+        callee: Value,
+        args: Vec<Value>,
+        span: &Span,
+        divisions: &[bool],
+    ) -> Result<Value, RuntimeError> {
+        if let Value::ToolRef { name, body, .. } = &callee
+            && let Some(tool) = self.defined.get(name)
+            && tool.body == *body
+        {
+            return self.call_compiled(program, tool.clone(), args, divisions);
+        }
+        self.runtime.division_args = divisions.to_vec();
+        self.runtime.call_value(callee, args, span)
+    }
+
+    fn call_compiled(
+        &mut self,
+        program: &CompiledProgram,
+        tool: Rc<CompiledTool>,
+        args: Vec<Value>,
+        divisions: &[bool],
+    ) -> Result<Value, RuntimeError> {
+        Interpreter::check_arguments(
+            &self.runtime.env,
+            &tool.name,
+            &tool.params,
+            &args,
+            divisions,
+        )?;
+        if self.depth >= self.max_call_depth {
+            return Err(RuntimeError::Custom(format!(
+                "stack overflow: max call depth of {} exceeded calling '{}'",
+                self.max_call_depth, tool.name
+            )));
+        }
+
+        let mut locals: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        locals.resize(tool.slots, None);
+        self.depth += 1;
+        let result = self.execute(program, &tool.code, &mut locals);
+        self.depth -= 1;
+        match (result, &tool.return_type) {
+            (Ok(value), Some(ty)) => {
+                let division = std::mem::take(&mut self.runtime.returned_division);
+                Interpreter::check_return_type(&self.runtime.env, &tool.name, ty, value, division)
+            }
+            (result, _) => result,
+        }
+    }
+}
+
+// the compiler keeps the stack balanced, so an empty pop is a compiler bug
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("bytecode stack underflow")
+}
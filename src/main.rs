@@ -1,38 +1,98 @@
-mod loquora;
-
 use std::env;
 use std::fs;
 use std::io;
 use std::io::Write;
 
-use loquora::interpreter::Interpreter;
-use loquora::lexer as lqlexer;
-use loquora::parser as lqparser;
-use loquora::token::TokenKind;
+use loquora::LoquoraError;
+use loquora::loquora::ast::{ParamDecl, StructMember, TypeExpr};
+use loquora::loquora::environment::{ToolDef, TypeDef};
+use loquora::loquora::interpreter::Interpreter;
+use loquora::loquora::lexer as lqlexer;
+use loquora::loquora::parser as lqparser;
+use loquora::loquora::token::TokenKind;
+use loquora::loquora::value::RuntimeError;
+use loquora::loquora::vm::{self, Vm};
+use loquora::loquora::{STACK_SIZE, parse_source};
+
+// long traces from runaway recursion are cut down to this many frames
+const MAX_TRACE_FRAMES: usize = 20;
 
 fn main() {
-    if let Some(path) = env::args().nth(1) {
-        if path.ends_with(".loq") {
-            let source = fs::read_to_string(&path).expect("Failed to read .loq file");
-            let lx = lqlexer::Lexer::new(source.clone());
-            let mut parser = lqparser::Parser::new(lx);
-            let program = parser.parse_program();
-
-            println!("=== AST ===");
-            println!("{:#?}", program);
-            println!();
-
-            println!("=== Interpretation ===");
-            let mut interpreter = Interpreter::new();
-            match interpreter.interpret_program(&program) {
-                Ok(result) => println!("Result: {}", result),
-                Err(error) => eprintln!("Runtime Error: {}", error),
-            }
+    let worker = std::thread::Builder::new()
+        .name("loquora".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("Failed to start interpreter thread");
+    if worker.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn run() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // restores the old behaviour where redeclaring a top-level tool or type overwrites it
+    let allow_shadowing = args.iter().any(|arg| arg == "--allow-shadowing");
+    let lint = args.iter().any(|arg| arg == "--lint");
+    // run on the bytecode VM when the program only uses what the compiler supports
+    let use_vm = args.iter().any(|arg| arg == "--vm");
+    // print the lexer's tokens and stop, for when a file doesn't lex the way it should
+    let tokens = args.iter().any(|arg| arg == "--tokens");
+
+    if let Some(path) = args.iter().find(|arg| arg.ends_with(".loq")) {
+        let source = fs::read_to_string(path).expect("Failed to read .loq file");
+        if tokens {
+            print_tokens(&source);
             return;
         }
+        let lx = lqlexer::Lexer::new(source.clone());
+        let mut parser = lqparser::Parser::new(lx);
+        let program = parser.parse_program();
+
+        println!("=== AST ===");
+        println!("{:#?}", program);
+        println!();
+
+        println!("=== Interpretation ===");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_allow_shadowing(allow_shadowing);
+        if lint {
+            for warning in interpreter.lint(&program) {
+                eprintln!(
+                    "warning: {} (line {})",
+                    warning.message,
+                    line_of(&source, warning.span.start)
+                );
+            }
+        }
+        if use_vm {
+            match vm::compile(&program) {
+                Ok(compiled) => {
+                    let mut machine = Vm::new();
+                    machine.set_allow_shadowing(allow_shadowing);
+                    match machine.run(&compiled) {
+                        Ok(result) => println!("Result: {}", result),
+                        Err(error) => eprintln!("Runtime Error: {}", error),
+                    }
+                    return;
+                }
+                Err(reason) => eprintln!("note: {}; running on the interpreter", reason),
+            }
+        }
+        match interpreter.interpret_program(&program) {
+            Ok(result) => println!("Result: {}", result),
+            Err(error) => report_runtime_error(&interpreter, &source, &error),
+        }
+        return;
     }
 
+    // one interpreter for the whole session so definitions carry over between inputs
+    let mut interpreter = Interpreter::new();
+    interpreter.set_allow_shadowing(allow_shadowing);
     let mut buffer = String::new();
+    // the last input that was evaluated, for meta-commands that look back at it
+    let mut last_input: Option<String> = None;
+    // a meta-command whose code continues on the following lines
+    let mut pending_command: Option<String> = None;
     loop {
         let prompt = if buffer.is_empty() { "spi> " } else { "...> " };
         let _ = io::stdout().write(prompt.as_bytes());
@@ -49,40 +109,285 @@ fn main() {
         {
             break;
         }
+        if buffer.is_empty() && trimmed.starts_with(':') {
+            let (command, code) = trimmed
+                .split_once(char::is_whitespace)
+                .unwrap_or((trimmed, ""));
+            let code = code.trim_start();
+            if has_open_delimiters(code) {
+                pending_command = Some(command.to_string());
+                buffer.push_str(code);
+                buffer.push('\n');
+                continue;
+            }
+            run_meta_command(&mut interpreter, command, code, last_input.as_deref());
+            continue;
+        }
 
         buffer.push_str(&line);
 
-        if !is_repl_input_complete(&buffer) {
+        // meta-command code is a single expression or snippet, so it only waits on brackets
+        let complete = if pending_command.is_some() {
+            !has_open_delimiters(&buffer)
+        } else {
+            is_repl_input_complete(&buffer)
+        };
+        if !complete {
             continue;
         }
 
         let source = buffer.clone();
         buffer.clear();
 
-        let lx = lqlexer::Lexer::new(source);
-        let mut parser = lqparser::Parser::new(lx);
-
-        let parsed =
-            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| parser.parse_program()));
+        if let Some(command) = pending_command.take() {
+            run_meta_command(&mut interpreter, &command, &source, last_input.as_deref());
+            continue;
+        }
 
-        match parsed {
-            Ok(program) => {
-                println!("=== AST ===");
-                println!("{:#?}", program);
-                println!();
+        match interpreter.eval_source(&source) {
+            Ok(result) => println!("Result: {}", result),
+            Err(error) => report_error(&interpreter, &source, &error),
+        }
+        last_input = Some(source);
+    }
+}
 
-                println!("=== Interpretation ===");
-                let mut interpreter = Interpreter::new();
-                match interpreter.interpret_program(&program) {
-                    Ok(result) => println!("Result: {}", result),
-                    Err(error) => eprintln!("Runtime Error: {}", error),
+// `:name code` lines in the REPL; only `:type` and `:load` run code in the session, and
+// nothing they run counts as the previous input
+fn run_meta_command(
+    interpreter: &mut Interpreter,
+    command: &str,
+    code: &str,
+    last_input: Option<&str>,
+) {
+    let rest = code.trim();
+    match command {
+        // `:ast code` parses `code`; a bare `:ast` shows the previous input again
+        ":ast" => {
+            let source = if rest.is_empty() {
+                match last_input {
+                    Some(source) => source,
+                    None => {
+                        eprintln!("No previous input to show.");
+                        return;
+                    }
                 }
+            } else {
+                rest
+            };
+            match parse_source(source) {
+                Ok(program) => println!("{:#?}", program),
+                Err(error) => report_error(interpreter, source, &error),
+            }
+        }
+        ":tokens" => print_tokens(rest),
+        ":load" => load_file(interpreter, rest),
+        // a fresh interpreter with the same command-line settings; the REPL's own state stays
+        ":reset" => {
+            let allow_shadowing = interpreter.env().allow_shadowing;
+            *interpreter = Interpreter::new();
+            interpreter.set_allow_shadowing(allow_shadowing);
+            println!("Session reset.");
+        }
+        ":clear-vars" => {
+            interpreter.env_mut().clear_frames();
+            println!("Variables cleared; tools and types are still defined.");
+        }
+        // `:env prefix` keeps only the names that start with `prefix`
+        ":env" => print_env(interpreter, rest),
+        // evaluates in the session like any input, but prints the type instead of the value
+        ":type" => {
+            if rest.is_empty() {
+                eprintln!("Usage: :type <expr>");
+                return;
+            }
+            match interpreter.eval_source(rest) {
+                Ok(value) => println!("{}", value.type_of()),
+                Err(error) => report_error(interpreter, rest, &error),
+            }
+        }
+        _ => eprintln!("Unknown command {}", command),
+    }
+}
+
+// runs a file in the session; loading it again replaces the tools and types it defined
+fn load_file(interpreter: &mut Interpreter, path: &str) {
+    if path.is_empty() {
+        eprintln!("Usage: :load <path>");
+        return;
+    }
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", path, error);
+            return;
+        }
+    };
+    let allow_shadowing = interpreter.env().allow_shadowing;
+    interpreter.set_allow_shadowing(true);
+    match interpreter.eval_source(&source) {
+        Ok(_) => println!("Loaded {}", path),
+        Err(error) => report_error(interpreter, &source, &error),
+    }
+    interpreter.set_allow_shadowing(allow_shadowing);
+}
+
+// longest value preview `:env` shows before cutting it off
+const ENV_PREVIEW_CHARS: usize = 60;
+
+fn print_env(interpreter: &Interpreter, prefix: &str) {
+    let env = interpreter.env();
+    let frames = env.frames();
+    for (depth, frame) in frames.iter().enumerate() {
+        let mut names: Vec<&String> = frame.keys().filter(|n| n.starts_with(prefix)).collect();
+        if names.is_empty() {
+            continue;
+        }
+        names.sort();
+        if frames.len() > 1 {
+            println!("variables (frame {}):", depth);
+        } else {
+            println!("variables:");
+        }
+        for name in names {
+            let value = &frame[name];
+            let mut preview = value.to_string();
+            if preview.chars().count() > ENV_PREVIEW_CHARS {
+                preview = preview.chars().take(ENV_PREVIEW_CHARS).collect::<String>() + "...";
+            }
+            println!("  {}: {} = {}", name, value.type_of(), preview);
+        }
+    }
+
+    let mut tools: Vec<&ToolDef> = env
+        .global_tools
+        .values()
+        .filter(|tool| tool.name.starts_with(prefix))
+        .collect();
+    tools.sort_by(|a, b| a.name.cmp(&b.name));
+    if !tools.is_empty() {
+        println!("tools:");
+    }
+    for tool in tools {
+        println!(
+            "  {}",
+            signature(&tool.name, &tool.params, &tool.return_type)
+        );
+    }
+
+    let mut types: Vec<(&String, &TypeDef)> = env
+        .type_definitions
+        .iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .collect();
+    types.sort_by(|a, b| a.0.cmp(b.0));
+    if !types.is_empty() {
+        println!("types:");
+    }
+    for (name, def) in types {
+        match def {
+            TypeDef::Struct { members, .. } => {
+                let members: Vec<String> = members
+                    .iter()
+                    .map(|member| match member {
+                        StructMember::Field(field) => format!(
+                            "{}: {}{}",
+                            field.name,
+                            field.ty.inner,
+                            field.suffix.as_deref().unwrap_or("")
+                        ),
+                        StructMember::ToolDecl {
+                            name,
+                            params,
+                            return_type,
+                            ..
+                        } => signature(name, params, return_type),
+                    })
+                    .collect();
+                println!("  struct {} {{ {} }}", name, members.join(", "));
+            }
+            TypeDef::Template { params, .. } => {
+                println!("  template {}", signature(name, params, &None));
             }
-            Err(_) => {
-                eprintln!("Parse error. Input was not a valid statement.");
+        }
+    }
+}
+
+// `name(a: Int, b: String = ...) -> Type`
+fn signature(name: &str, params: &[ParamDecl], return_type: &Option<TypeExpr>) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .map(|param| {
+            let default = if param.default.is_some() {
+                " = ..."
+            } else {
+                ""
+            };
+            format!("{}: {}{}", param.name, param.ty.inner, default)
+        })
+        .collect();
+    let mut signature = format!("{}({})", name, params.join(", "));
+    if let Some(ty) = return_type {
+        signature.push_str(&format!(" -> {}", ty.inner));
+    }
+    signature
+}
+
+// one token per line: kind, span and the source text it covers
+fn print_tokens(source: &str) {
+    let mut lexer = lqlexer::Lexer::new(source.to_string());
+    while let Some(tok) = lexer.next() {
+        println!(
+            "{:?} {}..{} {:?}",
+            tok.kind,
+            tok.span.start,
+            tok.span.end,
+            lexer.text(&tok)
+        );
+    }
+}
+
+fn report_error(interpreter: &Interpreter, source: &str, error: &LoquoraError) {
+    match error {
+        LoquoraError::Runtime(error) => report_runtime_error(interpreter, source, error),
+        // parse_source keeps the parser's panic quiet, so its message is only shown here
+        LoquoraError::Parse(message) => eprintln!("Parse error: {}", message),
+    }
+}
+
+fn report_runtime_error(interpreter: &Interpreter, source: &str, error: &RuntimeError) {
+    eprintln!("Runtime Error: {}", error);
+    let trace = interpreter.error_trace();
+    for frame in trace.iter().take(MAX_TRACE_FRAMES) {
+        match &frame.file {
+            None => eprintln!(
+                "  at {} (line {})",
+                frame.name,
+                line_of(source, frame.span.start)
+            ),
+            // a call inside a loaded module counts its line in that module's file
+            Some(file) => {
+                let cwd = env::current_dir().unwrap_or_default();
+                let shown = file.strip_prefix(&cwd).unwrap_or(file).display();
+                match fs::read_to_string(file) {
+                    Ok(module_source) => eprintln!(
+                        "  at {} ({} line {})",
+                        frame.name,
+                        shown,
+                        line_of(&module_source, frame.span.start)
+                    ),
+                    Err(_) => eprintln!("  at {} ({})", frame.name, shown),
+                }
             }
         }
     }
+    if trace.len() > MAX_TRACE_FRAMES {
+        eprintln!("  ... {} more frames", trace.len() - MAX_TRACE_FRAMES);
+    }
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source.chars().take(offset).filter(|c| *c == '\n').count() + 1
 }
 
 fn is_repl_input_complete(src: &str) -> bool {
@@ -91,38 +396,35 @@ fn is_repl_input_complete(src: &str) -> bool {
         return false;
     }
 
+    if has_open_delimiters(src) {
+        return false;
+    }
+
+    let last_sig = lqlexer::Lexer::new(src.to_string())
+        .map(|tok| tok.kind)
+        .filter(|kind| *kind != TokenKind::EOF)
+        .last();
+
+    matches!(
+        last_sig,
+        Some(TokenKind::Semicolon) | Some(TokenKind::RightBrace)
+    )
+}
+
+// a `(`, `[` or `{` that hasn't been closed yet
+fn has_open_delimiters(src: &str) -> bool {
     let mut paren_depth: isize = 0;
     let mut brace_depth: isize = 0;
 
-    let mut lx = lqlexer::Lexer::new(src.to_string());
-    let mut last_sig: Option<TokenKind> = None;
-    loop {
-        let tok = lx.next_token();
+    for tok in lqlexer::Lexer::new(src.to_string()) {
         match tok.kind {
-            TokenKind::LeftParen => paren_depth += 1,
-            TokenKind::RightParen => paren_depth -= 1,
-            TokenKind::LeftBrace => brace_depth += 1,
+            TokenKind::LeftParen | TokenKind::LeftBracket => paren_depth += 1,
+            TokenKind::RightParen | TokenKind::RightBracket => paren_depth -= 1,
+            TokenKind::LeftBrace | TokenKind::HashBrace => brace_depth += 1,
             TokenKind::RightBrace => brace_depth -= 1,
-            TokenKind::EOF => {
-                break;
-            }
             _ => {}
         }
-
-        match tok.kind {
-            TokenKind::EOF => {}
-            _ => {
-                last_sig = Some(tok.kind.clone());
-            }
-        }
     }
 
-    if paren_depth > 0 || brace_depth > 0 {
-        return false;
-    }
-
-    match last_sig {
-        Some(TokenKind::Semicolon) | Some(TokenKind::RightBrace) => true,
-        _ => false,
-    }
+    paren_depth > 0 || brace_depth > 0
 }